@@ -0,0 +1,221 @@
+// Compares the literal searches in the library with the line-at-a-time
+// implementations they replaced, and searching files through a memory map
+// with reading them into a buffer.
+//
+//   cargo bench                        # a generated 64 MiB corpus
+//   cargo bench -- FILE QUERY...       # your own file and queries
+//
+// Each search runs a few times and the fastest run is reported, along with
+// the throughput and how many lines matched (which should agree).
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+use minigrep::{
+    search, search_case_insensitive, AhoCorasick, FnSink, Literal, Match, Matcher, MmapChoice,
+    Searcher,
+};
+
+const RUNS: usize = 5;
+const CORPUS_SIZE: usize = 64 << 20;
+
+// The original `search`: one `contains` per line.
+fn old_search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    contents
+        .lines()
+        .filter(|line| line.contains(query))
+        .collect()
+}
+
+// The original `search_case_insensitive`: a lowercased copy of every line.
+fn old_search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    let query = query.to_lowercase();
+    contents
+        .lines()
+        .filter(|line| line.to_lowercase().contains(&query))
+        .collect()
+}
+
+// Lines of pseudo-random words, roughly like prose or a log file, with a
+// sprinkling of the words the default queries look for.
+fn corpus() -> String {
+    const WORDS: [&str; 16] = [
+        "the",
+        "quick",
+        "brown",
+        "fox",
+        "jumps",
+        "over",
+        "lazy",
+        "dog",
+        "safe",
+        "fast",
+        "productive",
+        "pick",
+        "three",
+        "duck",
+        "tape",
+        "Trust",
+    ];
+    // xorshift, so the corpus is the same on every run.
+    let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    let mut contents = String::with_capacity(CORPUS_SIZE + 128);
+    while contents.len() < CORPUS_SIZE {
+        let words = 4 + next() % 12;
+        for i in 0..words {
+            if i > 0 {
+                contents.push(' ');
+            }
+            let r = next();
+            // About one line in a thousand mentions the needle.
+            if r % 12_000 == 0 {
+                contents.push_str("needle");
+            } else {
+                contents.push_str(WORDS[(r % WORDS.len() as u64) as usize]);
+            }
+        }
+        contents.push('\n');
+    }
+    contents
+}
+
+fn bench(name: &str, bytes: usize, f: impl Fn() -> usize) {
+    let mut best = Duration::MAX;
+    let mut lines = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        lines = f();
+        best = best.min(start.elapsed());
+    }
+    let mib_per_sec = bytes as f64 / (1 << 20) as f64 / best.as_secs_f64();
+    println!(
+        "  {:<32} {:>10.2?} {:>10.0} MiB/s {:>10} lines",
+        name, best, mib_per_sec, lines
+    );
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`; anything else is ours.
+    let args: Vec<String> = env::args()
+        .skip(1)
+        .filter(|a| !a.starts_with("--"))
+        .collect();
+    let (path, contents, queries) = match args.split_first() {
+        Some((path, queries)) => {
+            let contents = fs::read_to_string(path).expect("couldn't read the corpus");
+            (Some(PathBuf::from(path)), contents, queries.to_vec())
+        }
+        None => (None, corpus(), Vec::new()),
+    };
+    let queries = if queries.is_empty() {
+        vec![
+            "needle".to_string(),
+            "productive".to_string(),
+            "e".to_string(),
+        ]
+    } else {
+        queries
+    };
+    println!(
+        "{:.1} MiB, {} lines",
+        contents.len() as f64 / (1 << 20) as f64,
+        contents.lines().count()
+    );
+
+    for query in &queries {
+        println!("query {:?}", query);
+        let literal = Literal::new(query);
+        bench("old search", contents.len(), || {
+            old_search(query, &contents).len()
+        });
+        bench("search (Horspool, whole buffer)", contents.len(), || {
+            search(&literal, &contents).len()
+        });
+        bench("old search_case_insensitive", contents.len(), || {
+            old_search_case_insensitive(query, &contents).len()
+        });
+        bench("search_case_insensitive", contents.len(), || {
+            search_case_insensitive(query, &contents).len()
+        });
+    }
+
+    // Many queries at once, as with `-f identifiers.txt`: a separate pass
+    // per query over each line, against one Aho-Corasick automaton.
+    let many: Vec<String> = (0..200).map(|i| format!("ident_{}", i)).collect();
+    let many = [&many[..], &queries[..]].concat();
+    println!("{} queries", many.len());
+    let literals: Vec<Literal> = many.iter().map(|q| Literal::new(q)).collect();
+    bench("one Literal per query", contents.len(), || {
+        contents
+            .lines()
+            .filter(|line| literals.iter().any(|l| l.is_match(line)))
+            .count()
+    });
+    let automaton = AhoCorasick::new(&many);
+    bench("AhoCorasick", contents.len(), || {
+        search(&automaton, &contents).len()
+    });
+
+    // The whole file through `Searcher::search_path`, as `minigrep` does.
+    let temp = env::temp_dir().join(format!("minigrep-bench-{}", process::id()));
+    let path = match path {
+        Some(path) => path,
+        None => {
+            fs::write(&temp, &contents).expect("couldn't write the corpus");
+            temp.clone()
+        }
+    };
+    let query = &queries[0];
+    println!("searching the file for {:?}", query);
+    bench_file("buffered (--no-mmap)", &path, query, MmapChoice::Never, 1);
+    bench_file(
+        "memory-mapped (--mmap)",
+        &path,
+        query,
+        MmapChoice::Always,
+        1,
+    );
+
+    // Smaller files, searched many times over, to see where a map stops
+    // paying for itself. `MmapChoice::Auto` only maps files of 1 MiB or
+    // more.
+    for size in [4 << 10, 64 << 10, 1 << 20, 8 << 20] {
+        let end = contents[..size.min(contents.len())]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        fs::write(&temp, &contents[..end]).expect("couldn't write the corpus");
+        let times = (64 << 20) / size;
+        println!("{} KiB file, {} times", end >> 10, times);
+        bench_file("buffered", &temp, query, MmapChoice::Never, times);
+        bench_file("memory-mapped", &temp, query, MmapChoice::Always, times);
+    }
+    let _ = fs::remove_file(&temp);
+}
+
+// Searches the file at `path` `times` times over with `mmap`.
+fn bench_file(name: &str, path: &Path, query: &str, mmap: MmapChoice, times: usize) {
+    let searcher = Searcher::new().with_mmap(mmap);
+    let bytes = fs::metadata(path).expect("couldn't stat the corpus").len() as usize;
+    bench(name, bytes * times, || {
+        let mut lines = 0;
+        let mut sink = FnSink(|_: &Path, _: &Match| -> io::Result<bool> {
+            lines += 1;
+            Ok(true)
+        });
+        for _ in 0..times {
+            searcher
+                .search_path(query, path, &mut sink)
+                .expect("couldn't search the corpus");
+        }
+        lines / times
+    });
+}
@@ -0,0 +1,248 @@
+use std::collections::VecDeque;
+
+use crate::casefold::fold;
+use crate::Matcher;
+
+const ROOT: usize = 0;
+
+// Searches for many literal patterns at once with an Aho-Corasick automaton.
+//
+// The patterns are put into a trie, and every state gets a failure link to
+// the longest proper suffix of its path that is also in the trie. Reading
+// the haystack then takes one transition per byte no matter how many
+// patterns there are, instead of one pass over the line per pattern.
+//
+// Like grep, a search returns the leftmost match, and the longest of the
+// matches starting there.
+pub struct AhoCorasick {
+    // `next[state * 256 + byte]` is the state after reading `byte`. The
+    // failure links are already folded into the table, so matching never
+    // has to follow them.
+    next: Vec<u32>,
+    // The length of the longest pattern that ends in each state, if any:
+    // chars when ignoring case, bytes otherwise.
+    longest: Vec<Option<usize>>,
+    // The length of the longest pattern, in the same units.
+    max_len: usize,
+    case_insensitive: bool,
+    has_newline: bool,
+}
+
+impl AhoCorasick {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> AhoCorasick {
+        let patterns = patterns.iter().map(|p| {
+            let p = p.as_ref();
+            (p.to_string(), p.len())
+        });
+        AhoCorasick::build(patterns, false)
+    }
+
+    // Ignores case using Unicode simple case folding, like
+    // `CaseInsensitive`: the patterns are folded up front and the haystack
+    // is folded one char at a time as it's read.
+    pub fn case_insensitive<S: AsRef<str>>(patterns: &[S]) -> AhoCorasick {
+        let patterns = patterns.iter().map(|p| {
+            let folded: String = p.as_ref().chars().map(fold).collect();
+            let len = folded.chars().count();
+            (folded, len)
+        });
+        AhoCorasick::build(patterns, true)
+    }
+
+    fn build<I>(patterns: I, case_insensitive: bool) -> AhoCorasick
+    where
+        I: Iterator<Item = (String, usize)>,
+    {
+        // First the trie. A transition to `ROOT` means there isn't one yet,
+        // which is unambiguous because nothing leads back to the root.
+        let mut next = vec![0u32; 256];
+        let mut longest = vec![None];
+        let mut max_len = 0;
+        let mut has_newline = false;
+        for (pattern, len) in patterns {
+            let mut state = ROOT;
+            for &b in pattern.as_bytes() {
+                let i = state * 256 + b as usize;
+                if next[i] == 0 {
+                    next[i] = longest.len() as u32;
+                    next.resize(next.len() + 256, 0);
+                    longest.push(None);
+                }
+                state = next[i] as usize;
+            }
+            longest[state] = Some(len);
+            max_len = max_len.max(len);
+            has_newline |= pattern.contains('\n');
+        }
+
+        // Then the failure links, breadth first so that a state's failure
+        // state (always shallower) is finished before the state itself.
+        let mut fail = vec![ROOT; longest.len()];
+        let mut queue: VecDeque<usize> = next[..256]
+            .iter()
+            .filter(|&&s| s != 0)
+            .map(|&s| s as usize)
+            .collect();
+        while let Some(state) = queue.pop_front() {
+            // A pattern ending at the failure state also ends here, but is
+            // shorter than one ending at this state itself.
+            if longest[state].is_none() {
+                longest[state] = longest[fail[state]];
+            }
+            for b in 0..256 {
+                let i = state * 256 + b;
+                let fallback = next[fail[state] * 256 + b];
+                if next[i] == 0 {
+                    next[i] = fallback;
+                } else {
+                    fail[next[i] as usize] = fallback as usize;
+                    queue.push_back(next[i] as usize);
+                }
+            }
+        }
+
+        AhoCorasick {
+            next,
+            longest,
+            max_len,
+            case_insensitive,
+            has_newline,
+        }
+    }
+
+    fn step(&self, state: usize, b: u8) -> usize {
+        self.next[state * 256 + b as usize] as usize
+    }
+
+    fn find_bytes(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        let mut best = self.longest[ROOT].map(|_| (start, start));
+        let mut state = ROOT;
+        for (i, &b) in haystack.as_bytes()[start..].iter().enumerate() {
+            let end = start + i + 1;
+            // Any match from here on starts after the best one so far.
+            if best.is_some_and(|(s, _)| end > s + self.max_len) {
+                break;
+            }
+            state = self.step(state, b);
+            if let Some(len) = self.longest[state] {
+                best = better((end - len, end), best);
+            }
+        }
+        best
+    }
+
+    fn find_folded(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        let mut best = self.longest[ROOT].map(|_| (start, start));
+        let mut state = ROOT;
+        // Where each of the last `max_len` chars starts, to turn a pattern's
+        // length in chars back into a span of the original haystack.
+        let mut starts = VecDeque::with_capacity(self.max_len + 1);
+        let mut buf = [0; 4];
+        for (i, c) in haystack[start..].char_indices() {
+            let offset = start + i;
+            starts.push_back(offset);
+            if starts.len() > self.max_len.max(1) {
+                starts.pop_front();
+            }
+            if best.is_some_and(|(s, _)| starts[0] > s) {
+                break;
+            }
+            for &b in fold(c).encode_utf8(&mut buf).as_bytes() {
+                state = self.step(state, b);
+            }
+            if let Some(len) = self.longest[state] {
+                let end = offset + c.len_utf8();
+                let begin = if len == 0 {
+                    end
+                } else {
+                    starts[starts.len() - len]
+                };
+                best = better((begin, end), best);
+            }
+        }
+        best
+    }
+}
+
+// Whichever of the two starts first, or the longer one if they start at the
+// same place.
+fn better(candidate: (usize, usize), best: Option<(usize, usize)>) -> Option<(usize, usize)> {
+    match best {
+        Some((s, e)) if s < candidate.0 || (s == candidate.0 && e >= candidate.1) => best,
+        _ => Some(candidate),
+    }
+}
+
+impl Matcher for AhoCorasick {
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        if self.case_insensitive {
+            self.find_folded(haystack, start)
+        } else {
+            self.find_bytes(haystack, start)
+        }
+    }
+
+    // Any match will do, so stop at the first state where a pattern ends.
+    fn is_match(&self, haystack: &str) -> bool {
+        if self.longest[ROOT].is_some() {
+            return true;
+        }
+        let mut state = ROOT;
+        if self.case_insensitive {
+            let mut buf = [0; 4];
+            haystack.chars().any(|c| {
+                for &b in fold(c).encode_utf8(&mut buf).as_bytes() {
+                    state = self.step(state, b);
+                }
+                self.longest[state].is_some()
+            })
+        } else {
+            haystack.bytes().any(|b| {
+                state = self.step(state, b);
+                self.longest[state].is_some()
+            })
+        }
+    }
+
+    fn matches_within_lines(&self) -> bool {
+        !self.has_newline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leftmost_longest() {
+        let m = AhoCorasick::new(&["he", "she", "his", "hers"]);
+        assert_eq!(Some((1, 4)), m.find_at("ushers", 0));
+        assert_eq!(Some((2, 6)), m.find_at("ushers", 2));
+        assert_eq!(vec![(0, 3), (5, 9)], m.find_all("his, hers"));
+        assert!(m.is_match("ushers"));
+        assert!(!m.is_match("hi, sh"));
+
+        // The longer pattern wins even though the shorter one ends first.
+        let m = AhoCorasick::new(&["abcd", "bc"]);
+        assert_eq!(Some((1, 5)), m.find_at("xabcd", 0));
+        assert_eq!(Some((1, 3)), m.find_at("abce", 0));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let m = AhoCorasick::case_insensitive(&["rUsT", "ΣΟΦ", "k"]);
+        assert_eq!(vec![(1, 5), (6, 12)], m.find_all("Trust σοφια"));
+        // 'K' (kelvin sign) is three bytes and folds to 'k'.
+        assert_eq!(Some((2, 5)), m.find_at("a \u{212A}", 0));
+        assert!(!m.is_match("rus"));
+    }
+
+    #[test]
+    fn empty_patterns() {
+        assert_eq!(None, AhoCorasick::new::<&str>(&[]).find_at("abc", 0));
+        let m = AhoCorasick::new(&["", "b"]);
+        assert_eq!(Some((0, 0)), m.find_at("abc", 0));
+        assert_eq!(Some((1, 2)), m.find_at("abc", 1));
+        assert!(m.is_match(""));
+    }
+}
@@ -0,0 +1,110 @@
+// Unicode simple case folding (the `C` and `S` entries of CaseFolding.txt).
+//
+// Simple folding maps every char to exactly one char, so a case-insensitive
+// match can be checked one char at a time and its span still points into
+// the original text. Full folding ('ß' -> "ss", 'İ' -> "i̇") would change
+// lengths, which is why lowercasing whole lines was the wrong tool.
+//
+// For almost every char the simple folding is its lowercase mapping. The
+// table below lists the chars where it isn't: ones that fold although they
+// are already lowercase (final sigma, long s, Greek symbol variants), and
+// scripts that fold to uppercase (Cherokee). Chars whose lowercase mapping
+// is more than one char, like 'İ', fold to themselves.
+const EXCEPTIONS: [(char, char); 31] = [
+    ('\u{00B5}', '\u{03BC}'), // µ micro sign -> μ
+    ('\u{017F}', 's'),        // ſ long s
+    ('\u{0345}', '\u{03B9}'), // combining ypogegrammeni -> ι
+    ('\u{03C2}', '\u{03C3}'), // ς final sigma -> σ
+    ('\u{03D0}', '\u{03B2}'), // ϐ -> β
+    ('\u{03D1}', '\u{03B8}'), // ϑ -> θ
+    ('\u{03D5}', '\u{03C6}'), // ϕ -> φ
+    ('\u{03D6}', '\u{03C0}'), // ϖ -> π
+    ('\u{03F0}', '\u{03BA}'), // ϰ -> κ
+    ('\u{03F1}', '\u{03C1}'), // ϱ -> ρ
+    ('\u{03F5}', '\u{03B5}'), // ϵ -> ε
+    ('\u{13F8}', '\u{13F0}'), // Cherokee small letters fold to capitals
+    ('\u{13F9}', '\u{13F1}'),
+    ('\u{13FA}', '\u{13F2}'),
+    ('\u{13FB}', '\u{13F3}'),
+    ('\u{13FC}', '\u{13F4}'),
+    ('\u{13FD}', '\u{13F5}'),
+    ('\u{1C80}', '\u{0432}'), // Cyrillic rounded ve -> в
+    ('\u{1C81}', '\u{0434}'), // long-legged de -> д
+    ('\u{1C82}', '\u{043E}'), // narrow o -> о
+    ('\u{1C83}', '\u{0441}'), // wide es -> с
+    ('\u{1C84}', '\u{0442}'), // tall te -> т
+    ('\u{1C85}', '\u{0442}'), // three-legged te -> т
+    ('\u{1C86}', '\u{044A}'), // tall hard sign -> ъ
+    ('\u{1C87}', '\u{0463}'), // tall yat -> ѣ
+    ('\u{1C88}', '\u{A64B}'), // unblended uk -> ꙋ
+    ('\u{1E9B}', '\u{1E61}'), // ẛ -> ṡ
+    ('\u{1FBE}', '\u{03B9}'), // Greek prosgegrammeni -> ι
+    ('\u{1FD3}', '\u{0390}'), // ΐ (oxia) -> ΐ (tonos)
+    ('\u{1FE3}', '\u{03B0}'), // ΰ (oxia) -> ΰ (tonos)
+    ('\u{FB05}', '\u{FB06}'), // ﬅ -> ﬆ
+];
+
+// The rest of the Cherokee small letters, U+AB70..=U+ABBF, fold to
+// U+13A0..=U+13EF. The capitals themselves fold to themselves, even though
+// their lowercase mapping points the other way.
+const CHEROKEE_CAPITALS: (u32, u32) = (0x13A0, 0x13F5);
+const CHEROKEE_SMALL: (u32, u32) = (0xAB70, 0xABBF);
+const CHEROKEE_OFFSET: u32 = 0xAB70 - 0x13A0;
+
+pub fn fold(c: char) -> char {
+    if c.is_ascii() {
+        return c.to_ascii_lowercase();
+    }
+    if let Ok(i) = EXCEPTIONS.binary_search_by_key(&c, |&(from, _)| from) {
+        return EXCEPTIONS[i].1;
+    }
+    let code = c as u32;
+    if CHEROKEE_CAPITALS.0 <= code && code <= CHEROKEE_CAPITALS.1 {
+        return c;
+    }
+    if CHEROKEE_SMALL.0 <= code && code <= CHEROKEE_SMALL.1 {
+        return char::from_u32(code - CHEROKEE_OFFSET).unwrap_or(c);
+    }
+    let mut lower = c.to_lowercase();
+    if lower.len() == 1 {
+        lower.next().unwrap()
+    } else {
+        c
+    }
+}
+
+// Whether `a` and `b` are equal under simple case folding.
+pub fn eq(a: char, b: char) -> bool {
+    a == b || fold(a) == fold(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_is_sorted() {
+        let mut sorted = EXCEPTIONS;
+        sorted.sort_by_key(|&(from, _)| from);
+        assert_eq!(sorted, EXCEPTIONS);
+    }
+
+    #[test]
+    fn folds() {
+        assert!(eq('A', 'a'));
+        assert!(eq('Ä', 'ä'));
+        assert!(eq('Σ', 'ς'));
+        assert!(eq('σ', 'ς'));
+        assert!(eq('ſ', 'S'));
+        assert!(eq('\u{212A}', 'K'));
+        assert!(eq('µ', 'Μ'));
+        assert!(eq('ẞ', 'ß'));
+        assert!(eq('\u{AB70}', '\u{13A0}'));
+        assert_eq!('\u{13A0}', fold('\u{13A0}'));
+        assert_eq!('\u{13F0}', fold('\u{13F8}'));
+        // Full folding would turn these into two chars; simple folding
+        // leaves them alone.
+        assert!(!eq('İ', 'i'));
+        assert!(!eq('ß', 's'));
+    }
+}
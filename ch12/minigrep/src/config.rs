@@ -0,0 +1,1248 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, IsTerminal};
+
+use crate::config_file::ConfigFile;
+use crate::printer::Colors;
+use crate::types;
+
+pub const USAGE: &str = "\
+Usage: minigrep [OPTIONS] PATTERN [FILE]...
+       minigrep [OPTIONS] -e PATTERN... [-f PATTERN_FILE]... [FILE]...
+       minigrep index DIR...
+
+Search each FILE (or directory, recursively) for lines matching any
+PATTERN. With no FILE, or when FILE is -, read standard input.
+Compressed files (gzip, and bzip2 or xz if those programs are installed)
+are searched as if they had been decompressed.
+
+`minigrep index DIR` builds an index of the files in DIR, or brings it up
+to date, so that `--index` searches of DIR can skip the files that can't
+match. To search for the word index, use `-e index` or `-- index`.
+
+Options:
+  -e, --regexp PATTERN       use PATTERN as a query; may be repeated
+  -f, --file PATTERN_FILE    read queries from PATTERN_FILE, one per line
+  -E, --extended-regexp      treat the query as a regular expression
+  -Q, --query                treat the query as a boolean expression of
+                             terms, e.g. 'error AND (timeout OR refused)
+                             AND NOT retry'
+  -i, --ignore-case          ignore case distinctions
+  -s, --case-sensitive       match case even if CASE_INSENSITIVE is set
+  -w, --word-regexp          only match whole words
+  -x, --line-regexp          only match whole lines
+      --fuzzy K              match the query with up to K inserted, deleted
+                             or substituted chars; each line is prefixed
+                             with how many edits it needed
+  -v, --invert-match         select non-matching lines
+  -U, --multiline            let matches span lines; every line a match
+                             touches is printed
+  -p, --paragraph            search and print paragraphs (separated by blank
+                             lines) rather than lines
+  -z, --null-data            records are separated by NUL rather than newline
+      --record-separator SEP
+                             records are separated by SEP, which can use
+                             \\n, \\t, \\0 and \\\\ escapes
+  -a, --text                 search binary files as if they were text
+  -I, --skip-binary          skip binary files
+      --binary-files=TYPE    binary (the default: just say whether a binary
+                             file matches), text or without-match
+  -c, --count                print only a count of selected lines per file
+  -l, --files-with-matches   print only the names of files with selected lines
+  -L, --files-without-match  print only the names of files with no selected lines
+  -o, --only-matching        print only the matched parts of each line
+  -n, --line-number          prefix each line with its line number
+  -A, --after-context N      print N lines of trailing context
+  -B, --before-context N     print N lines of leading context
+  -C, --context N            print N lines of leading and trailing context
+      --replace TEXT         print selected lines with every match replaced
+                             by TEXT
+      --in-place             with --replace, rewrite the files themselves
+      --dry-run              with --replace, print a unified diff of what
+                             --in-place would change
+      --json                 print results as JSON Lines: begin, match,
+                             context and end events per file, then a
+                             summary
+      --color[=WHEN]         highlight matches; WHEN is auto (the default
+                             when given without a value), always or never
+  -j, --threads N            search files on N threads (default: one per CPU)
+      --index                only search the files in each DIR that its index
+                             says could match a plain-text PATTERN, updating
+                             the index first for files that have changed
+      --mmap                 search files through a memory map, even small
+                             or recently changed ones; a file truncated
+                             during the search crashes minigrep
+      --no-mmap              never memory-map files; read them into a
+                             buffer instead
+      --watch                keep running, and print new matches as the
+                             files are appended to, rewritten or rotated;
+                             there are no totals, so no --json
+  -g, --glob GLOB            only search files in directories that match
+                             GLOB, or with !GLOB, that don't; may be repeated,
+                             and overrides ignore files
+  -t, --type NAME            only search files of type NAME (rust, markdown,
+                             py, ... or one from the config file's [types]);
+                             may be repeated
+  -T, --type-not NAME        don't search files of type NAME
+      --no-config            don't read the config file
+      --no-FLAG              turn off a FLAG given earlier, e.g. in the
+                             config file: --no-json, --no-count, --no-watch,
+                             --no-line-number, --no-ignore-case, ...
+  -r, --recursive            accepted for grep compatibility; directories
+                             are always searched recursively
+  -h, --help                 print this help and exit
+  -V, --version              print version information and exit
+
+Environment:
+  CASE_INSENSITIVE   ignore case unless -s is given
+  MINIGREP_CONFIG    the config file to read instead of
+                     ~/.config/minigrep/config.toml; empty for none
+  NO_COLOR           disable --color=auto
+  REGEX              treat the query as a regular expression";
+
+// Short flags and the long option each one is an alias for.
+const SHORT_FLAGS: [(char, &str); 29] = [
+    ('e', "regexp"),
+    ('f', "file"),
+    ('E', "extended-regexp"),
+    ('Q', "query"),
+    ('i', "ignore-case"),
+    ('s', "case-sensitive"),
+    ('w', "word-regexp"),
+    ('x', "line-regexp"),
+    ('v', "invert-match"),
+    ('U', "multiline"),
+    ('p', "paragraph"),
+    ('z', "null-data"),
+    ('a', "text"),
+    ('I', "skip-binary"),
+    ('c', "count"),
+    ('l', "files-with-matches"),
+    ('L', "files-without-match"),
+    ('o', "only-matching"),
+    ('n', "line-number"),
+    ('A', "after-context"),
+    ('B', "before-context"),
+    ('C', "context"),
+    ('h', "help"),
+    ('V', "version"),
+    ('r', "recursive"),
+    ('t', "type"),
+    ('T', "type-not"),
+    ('g', "glob"),
+    ('j', "threads"),
+];
+
+// Long options whose value is optional and can only be given inline, as
+// `--opt=value`, and the value they get without one.
+const OPTIONAL_VALUE: [(&str, &str); 2] = [("color", "auto"), ("colour", "auto")];
+
+// Long options that take a value, either as `--opt value` or `--opt=value`.
+const TAKES_VALUE: [&str; 13] = [
+    "regexp",
+    "fuzzy",
+    "binary-files",
+    "record-separator",
+    "replace",
+    "file",
+    "after-context",
+    "before-context",
+    "context",
+    "threads",
+    "type",
+    "type-not",
+    "glob",
+];
+
+// Flags that `--no-FLAG` turns off again, so that one set in the config
+// file can be undone on the command line.
+const NEGATABLE: [&str; 15] = [
+    "extended-regexp",
+    "query",
+    "ignore-case",
+    "word-regexp",
+    "line-regexp",
+    "invert-match",
+    "multiline",
+    "count",
+    "files-with-matches",
+    "files-without-match",
+    "only-matching",
+    "line-number",
+    "json",
+    "watch",
+    "index",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    // `--help` or `--version` was given; not a failure, but parsing stops.
+    Help,
+    Version,
+    MissingPattern,
+    // `minigrep index` with nothing to index.
+    MissingDirectory,
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidNumber(String, String),
+    InvalidColor(String),
+    InvalidBinaryFiles(String),
+    UnexpectedValue(String),
+    // A `-f` file couldn't be read: its path and the reason.
+    PatternFile(String, String),
+    // The config file is broken: its path and what's wrong.
+    ConfigFile(String, String),
+    // `--type` named a type that isn't defined.
+    UnknownType(String),
+    // `--record-separator` was given an empty string.
+    EmptySeparator,
+    // Two options that can't be used together.
+    Conflict(String, String),
+    // The first option only makes sense along with the second.
+    Requires(String, String),
+}
+
+// What `run` prints for each file. `-v` is separate: it changes which lines
+// are selected, not how they are reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputMode {
+    // Every selected line (the default).
+    Lines,
+    // `-o`: each match on its own line instead of the whole line.
+    OnlyMatching,
+    // `-c`: the number of selected lines.
+    Count,
+    // `-l`: the file name, if any line was selected.
+    FilesWithMatches,
+    // `-L`: the file name, if no line was selected.
+    FilesWithoutMatch,
+}
+
+impl OutputMode {
+    // When several modes are asked for, the one that prints the least wins,
+    // as in grep: `-l` beats `-c`, which beats `-o`.
+    fn rank(self) -> u8 {
+        match self {
+            OutputMode::Lines => 0,
+            OutputMode::OnlyMatching => 1,
+            OutputMode::Count => 2,
+            OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch => 3,
+        }
+    }
+
+    // The flag that selects this mode.
+    fn flag(self) -> Option<&'static str> {
+        match self {
+            OutputMode::Lines => None,
+            OutputMode::OnlyMatching => Some("-o"),
+            OutputMode::Count => Some("-c"),
+            OutputMode::FilesWithMatches => Some("-l"),
+            OutputMode::FilesWithoutMatch => Some("-L"),
+        }
+    }
+}
+
+// What to do with a file that has a NUL byte in it, which text never does.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BinaryFiles {
+    // Search it, but only say whether it matched, as
+    // "Binary file X matches", rather than print its lines.
+    #[default]
+    Binary,
+    // `-a`: search and print it like any other file.
+    Text,
+    // `-I`: don't search it at all.
+    WithoutMatch,
+}
+
+// What a search matches against and prints: lines, unless asked for
+// something longer.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Records {
+    #[default]
+    Lines,
+    // `-p`: paragraphs, separated by one or more blank lines.
+    Paragraphs,
+    // `-z` (a NUL) or `--record-separator SEP`.
+    Separator(Vec<u8>),
+}
+
+impl Records {
+    // What's printed after each record: the same thing that separated them
+    // in the input, so the output can be split up the same way.
+    pub fn terminator(&self) -> &[u8] {
+        match self {
+            Records::Lines => b"\n",
+            Records::Paragraphs => b"\n\n",
+            Records::Separator(sep) => sep,
+        }
+    }
+
+    // The flag that picked these records, for error messages.
+    fn flag(&self) -> Option<&'static str> {
+        match self {
+            Records::Lines => None,
+            Records::Paragraphs => Some("-p"),
+            Records::Separator(sep) if sep == b"\0" => Some("-z"),
+            Records::Separator(_) => Some("--record-separator"),
+        }
+    }
+}
+
+// Whether to search a file through a memory map or read it into a buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MmapChoice {
+    // Map files big enough that it's likely to pay off, and that haven't
+    // been written to lately; see `Searcher`.
+    #[default]
+    Auto,
+    // `--mmap`: map every file that can be.
+    Always,
+    // `--no-mmap`.
+    Never,
+}
+
+// `--color=WHEN`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    // Color when stdout is a terminal and `NO_COLOR` isn't set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                // https://no-color.org: any non-empty value disables color.
+                let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+                !no_color && io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Help => write!(f, "{}", USAGE),
+            ConfigError::Version => write!(f, "minigrep {}", env!("CARGO_PKG_VERSION")),
+            ConfigError::MissingPattern => write!(f, "Didn't get a query string"),
+            ConfigError::MissingDirectory => write!(f, "Didn't get a directory to index"),
+            ConfigError::UnknownFlag(flag) => write!(f, "Unknown option '{}'", flag),
+            ConfigError::MissingValue(flag) => write!(f, "Option '{}' needs a value", flag),
+            ConfigError::InvalidNumber(flag, value) => {
+                write!(f, "Option '{}' expects a number, got '{}'", flag, value)
+            }
+            ConfigError::InvalidColor(value) => write!(
+                f,
+                "Invalid --color value '{}', expected auto, always or never",
+                value
+            ),
+            ConfigError::InvalidBinaryFiles(value) => write!(
+                f,
+                "Invalid --binary-files value '{}', expected binary, text or without-match",
+                value
+            ),
+            ConfigError::UnexpectedValue(flag) => {
+                write!(f, "Option '{}' doesn't take a value", flag)
+            }
+            ConfigError::PatternFile(path, reason) => {
+                write!(f, "Couldn't read patterns from '{}': {}", path, reason)
+            }
+            ConfigError::ConfigFile(path, reason) => {
+                write!(f, "Error in config file '{}': {}", path, reason)
+            }
+            ConfigError::UnknownType(name) => write!(f, "Unknown file type '{}'", name),
+            ConfigError::EmptySeparator => write!(f, "The record separator can't be empty"),
+            ConfigError::Conflict(a, b) => {
+                write!(f, "Options '{}' and '{}' can't be used together", a, b)
+            }
+            ConfigError::Requires(a, b) => write!(f, "Option '{}' requires '{}'", a, b),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    // A line is selected if it matches any of these.
+    pub queries: Vec<String>,
+    // Files to search; directories are searched recursively and `-` (or no
+    // paths at all) means stdin.
+    pub paths: Vec<String>,
+    pub case_sensitive: bool,
+    // Treat `query` as a regular expression instead of a literal string.
+    pub regex: bool,
+    // `-Q`: treat `query` as a boolean expression of terms.
+    pub query: bool,
+    // `-w`: a match must not have a word char (a letter, digit or `_`)
+    // right before or after it.
+    pub word_regexp: bool,
+    // `-x`: a match must be the whole line. Takes precedence over `-w`.
+    pub line_regexp: bool,
+    // `--fuzzy K`: approximate matching, allowing up to K edits.
+    pub fuzzy: Option<usize>,
+    // `-v`: select the lines that don't match.
+    pub invert_match: bool,
+    // `-U`: search each file as a whole, so a match can span lines.
+    pub multiline: bool,
+    pub records: Records,
+    pub binary_files: BinaryFiles,
+    pub output: OutputMode,
+    // `-n`: prefix each line with its line number.
+    pub line_number: bool,
+    // `-B N` / `-A N`: lines of context to print before / after a match.
+    // `-C N` sets both.
+    pub before_context: usize,
+    pub after_context: usize,
+    pub color: ColorChoice,
+    pub colors: Colors,
+    // `--json`: JSON Lines output instead of grep-style text.
+    pub json: bool,
+    // `--replace TEXT`: what to substitute for each match.
+    pub replace: Option<String>,
+    // `--in-place`: write the replaced text back to the files.
+    pub in_place: bool,
+    // `--dry-run`: show the changes `--in-place` would make as a diff.
+    pub dry_run: bool,
+    // `-j N`: worker threads for searching several files; 0 picks one per
+    // CPU.
+    pub threads: usize,
+    // `--mmap` / `--no-mmap`.
+    pub mmap: MmapChoice,
+    // `--watch`: keep searching the files as they change, like `tail -F`.
+    pub watch: bool,
+    // `minigrep index DIR...`: build or update the index of each of
+    // `paths`, rather than search them.
+    pub build_index: bool,
+    // `--index`: narrow down the files to search with the directories'
+    // indexes.
+    pub index: bool,
+    // Globs for files to leave out when searching a directory, as if they
+    // were in a `.gitignore` at its top.
+    pub ignore: Vec<String>,
+    // Named sets of file globs: the built-in ones, and any from the config
+    // file.
+    pub file_types: Vec<(String, Vec<String>)>,
+    // `-t NAME`: only search files found in a directory that match one of
+    // these types. Files named on the command line are always searched.
+    pub types: Vec<String>,
+    // `-T NAME`: skip files of these types.
+    pub types_not: Vec<String>,
+    // `-g GLOB`: include or (with `!`) exclude files found in a directory.
+    pub globs: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            queries: Vec::new(),
+            paths: Vec::new(),
+            case_sensitive: true,
+            regex: false,
+            query: false,
+            word_regexp: false,
+            line_regexp: false,
+            fuzzy: None,
+            invert_match: false,
+            multiline: false,
+            records: Records::Lines,
+            binary_files: BinaryFiles::Binary,
+            output: OutputMode::Lines,
+            line_number: false,
+            before_context: 0,
+            after_context: 0,
+            color: ColorChoice::Auto,
+            colors: Colors::default(),
+            json: false,
+            replace: None,
+            in_place: false,
+            dry_run: false,
+            threads: 0,
+            mmap: MmapChoice::Auto,
+            watch: false,
+            build_index: false,
+            index: false,
+            ignore: Vec::new(),
+            file_types: types::built_in(),
+            types: Vec::new(),
+            types_not: Vec::new(),
+            globs: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn new<I>(args: I) -> Result<Config, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        // Returning Result, instead of panicking, allows `main` to handle
+        // the Result, and exit the process more cleanly
+        let args: Vec<String> = args.into_iter().skip(1).collect();
+        // `--no-config` has to be spotted before any flags are applied,
+        // since the ones in the file come first.
+        let file = if has_no_config(&args) {
+            None
+        } else {
+            ConfigFile::load()?
+        };
+        Config::with_file(args, file)
+    }
+
+    // Like `new`, but with the config file already loaded (or not), and
+    // without the program name at the start of `args`.
+    pub fn with_file(
+        mut args: Vec<String>,
+        file: Option<ConfigFile>,
+    ) -> Result<Config, ConfigError> {
+        // The environment variables are only defaults; flags override them.
+        let mut config = Config {
+            case_sensitive: env::var("CASE_INSENSITIVE").is_err(),
+            regex: env::var("REGEX").is_ok(),
+            ..Config::default()
+        };
+        // `index` is a command only as the very first argument; anywhere
+        // else, it's a query or a file like any other word.
+        if args.first().map(String::as_str) == Some("index") {
+            args.remove(0);
+            config.build_index = true;
+        }
+        // `None` until a `-e` or `-f` is seen; otherwise the first positional
+        // argument is the query.
+        let mut patterns = None;
+        let mut positional = Vec::new();
+
+        // The file's flags go first, as if they'd been typed before the
+        // command line's, so that the command line wins.
+        if let Some(file) = file {
+            let error =
+                |reason: String| ConfigError::ConfigFile(file.path.display().to_string(), reason);
+            config
+                .parse_args(file.flags.iter().cloned(), &mut patterns, &mut positional)
+                .map_err(|e| error(format!("in 'flags': {}", e)))?;
+            if patterns.is_some() || !positional.is_empty() {
+                let reason = "'flags' can't include patterns or files to search";
+                return Err(error(reason.to_string()));
+            }
+            config.colors = file.colors;
+            config.ignore = file.ignore;
+            types::define(&mut config.file_types, file.types);
+        }
+        config.parse_args(args.into_iter(), &mut patterns, &mut positional)?;
+
+        let mut positional = positional.into_iter();
+        if config.build_index {
+            config.paths = positional.collect();
+            if config.paths.is_empty() {
+                return Err(ConfigError::MissingDirectory);
+            }
+            return Ok(config);
+        }
+        config.queries = match patterns {
+            Some(patterns) => patterns,
+            None => vec![positional.next().ok_or(ConfigError::MissingPattern)?],
+        };
+        config.paths = positional.collect();
+
+        config.check()?;
+        Ok(config)
+    }
+
+    // Applies the flags in `args`, and collects everything else (the query
+    // and the paths) in `positional`.
+    fn parse_args<I>(
+        &mut self,
+        mut args: I,
+        patterns: &mut Option<Vec<String>>,
+        positional: &mut Vec<String>,
+    ) -> Result<(), ConfigError>
+    where
+        I: Iterator<Item = String>,
+    {
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                positional.extend(args.by_ref());
+                break;
+            } else if let Some(long) = arg.strip_prefix("--") {
+                let (name, inline) = match long.find('=') {
+                    Some(i) => (&long[..i], Some(long[i + 1..].to_string())),
+                    None => (long, None),
+                };
+                let optional = OPTIONAL_VALUE.iter().find(|&&(opt, _)| opt == name);
+                let value = if let Some(&(_, default)) = optional {
+                    Some(inline.unwrap_or_else(|| default.to_string()))
+                } else if TAKES_VALUE.contains(&name) {
+                    match inline {
+                        Some(value) => Some(value),
+                        None => Some(
+                            args.next()
+                                .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?,
+                        ),
+                    }
+                } else if inline.is_some() {
+                    return Err(ConfigError::UnexpectedValue(format!("--{}", name)));
+                } else {
+                    None
+                };
+                self.apply(name, value, patterns)?;
+            } else if arg.len() > 1 && arg.starts_with('-') {
+                // Short flags can be bundled (`-in`), and the last one may
+                // take its value from the rest of the word (`-A3`) or from
+                // the next argument (`-A 3`).
+                let flags: Vec<char> = arg[1..].chars().collect();
+                for (i, &flag) in flags.iter().enumerate() {
+                    let name = SHORT_FLAGS
+                        .iter()
+                        .find(|&&(short, _)| short == flag)
+                        .map(|&(_, long)| long)
+                        .ok_or_else(|| ConfigError::UnknownFlag(format!("-{}", flag)))?;
+                    if TAKES_VALUE.contains(&name) {
+                        let rest: String = flags[i + 1..].iter().collect();
+                        let value = if rest.is_empty() {
+                            args.next()
+                                .ok_or_else(|| ConfigError::MissingValue(format!("-{}", flag)))?
+                        } else {
+                            rest
+                        };
+                        self.apply(name, Some(value), patterns)?;
+                        break;
+                    }
+                    self.apply(name, None, patterns)?;
+                }
+            } else {
+                positional.push(arg);
+            }
+        }
+        Ok(())
+    }
+
+    // Rejects combinations of options that don't make sense together.
+    fn check(&self) -> Result<(), ConfigError> {
+        let conflict = |a: &str, b: &str| Err(ConfigError::Conflict(a.to_string(), b.to_string()));
+        let rewrite = if self.dry_run {
+            Some("--dry-run")
+        } else if self.in_place {
+            Some("--in-place")
+        } else {
+            None
+        };
+        if let Some(rewrite) = rewrite {
+            if self.replace.is_none() {
+                return Err(ConfigError::Requires(
+                    rewrite.to_string(),
+                    "--replace".to_string(),
+                ));
+            }
+            if let Some(flag) = self.output.flag() {
+                return conflict(rewrite, flag);
+            }
+            // Files are rewritten a line at a time.
+            if let Some(flag) = self.records.flag() {
+                return conflict(rewrite, flag);
+            }
+        }
+        // Lines that didn't match have nothing to replace.
+        if self.replace.is_some() && self.invert_match {
+            return conflict("--replace", "-v");
+        }
+        // A fuzzy query is a plain string, and its matches have no fixed
+        // length to check word or line boundaries against.
+        if self.fuzzy.is_some() {
+            for (set, flag) in [
+                (self.regex, "-E"),
+                (self.word_regexp, "-w"),
+                (self.line_regexp, "-x"),
+            ] {
+                if set {
+                    return conflict("--fuzzy", flag);
+                }
+            }
+        }
+        // Every `-t` has to name a type that's been defined.
+        if let Some(name) = self
+            .types
+            .iter()
+            .chain(&self.types_not)
+            .find(|&name| !self.file_types.iter().any(|(defined, _)| defined == name))
+        {
+            return Err(ConfigError::UnknownType(name.clone()));
+        }
+        // A boolean query's terms are plain text, and it's true or false of
+        // a line as a whole, not of the whole file or a rewrite.
+        if self.query {
+            let other = [
+                (self.regex, "-E"),
+                (self.fuzzy.is_some(), "--fuzzy"),
+                (self.multiline, "-U"),
+                (self.in_place, "--in-place"),
+                (self.dry_run, "--dry-run"),
+            ];
+            if let Some(&(_, flag)) = other.iter().find(|&&(set, _)| set) {
+                return conflict("--query", flag);
+            }
+        }
+        // Records can already span lines; `-U` is for when there are none.
+        if let Some(flag) = self.records.flag() {
+            if self.multiline {
+                return conflict("-U", flag);
+            }
+            // Watching follows files a line at a time.
+            if self.watch {
+                return conflict("--watch", flag);
+            }
+        }
+        if self.multiline && self.watch {
+            return conflict("--watch", "-U");
+        }
+        // Watching searches the files as they're written, which the index
+        // would have to keep up with.
+        if self.index && self.watch {
+            return conflict("--watch", "--index");
+        }
+        // A count or a file name is only known once a whole file has been
+        // searched, and a watched file is never done. Neither is the search,
+        // so there are no totals for `--json`'s summary.
+        if self.watch {
+            if self.json {
+                return conflict("--watch", "--json");
+            }
+            if let Some(flag) = rewrite.or(match self.output {
+                OutputMode::Lines | OutputMode::OnlyMatching => None,
+                output => output.flag(),
+            }) {
+                return conflict("--watch", flag);
+            }
+        }
+        // JSON output reports every matching line as it is in the file;
+        // there's no JSON for a bare count or file name, or a rewrite.
+        if self.json {
+            if let Some(flag) = self.output.flag().or(rewrite) {
+                return conflict("--json", flag);
+            }
+            if self.replace.is_some() {
+                return conflict("--json", "--replace");
+            }
+        }
+        Ok(())
+    }
+
+    // Records one option, named by its long form.
+    fn apply(
+        &mut self,
+        name: &str,
+        value: Option<String>,
+        patterns: &mut Option<Vec<String>>,
+    ) -> Result<(), ConfigError> {
+        match name {
+            "regexp" => patterns.get_or_insert_with(Vec::new).extend(value),
+            "file" => {
+                let path = value.unwrap_or_default();
+                let contents = fs::read_to_string(&path)
+                    .map_err(|e| ConfigError::PatternFile(path, e.to_string()))?;
+                // An empty file means no patterns, which matches nothing.
+                let patterns = patterns.get_or_insert_with(Vec::new);
+                patterns.extend(contents.lines().map(String::from));
+            }
+            "extended-regexp" => self.regex = true,
+            "query" => self.query = true,
+            "ignore-case" => self.case_sensitive = false,
+            "case-sensitive" => self.case_sensitive = true,
+            "word-regexp" => self.word_regexp = true,
+            "line-regexp" => self.line_regexp = true,
+            "fuzzy" => self.fuzzy = Some(parse_number(name, value)?),
+            "invert-match" => self.invert_match = true,
+            "multiline" => self.multiline = true,
+            "paragraph" => self.records = Records::Paragraphs,
+            "null-data" => self.records = Records::Separator(vec![0]),
+            "record-separator" => {
+                let sep = unescape(&value.unwrap_or_default());
+                if sep.is_empty() {
+                    return Err(ConfigError::EmptySeparator);
+                }
+                self.records = Records::Separator(sep);
+            }
+            "text" => self.binary_files = BinaryFiles::Text,
+            "skip-binary" => self.binary_files = BinaryFiles::WithoutMatch,
+            "binary-files" => {
+                self.binary_files = match value.as_deref() {
+                    Some("binary") => BinaryFiles::Binary,
+                    Some("text") => BinaryFiles::Text,
+                    Some("without-match") => BinaryFiles::WithoutMatch,
+                    _ => return Err(ConfigError::InvalidBinaryFiles(value.unwrap_or_default())),
+                }
+            }
+            "only-matching" => self.set_output(OutputMode::OnlyMatching),
+            "count" => self.set_output(OutputMode::Count),
+            "files-with-matches" => self.set_output(OutputMode::FilesWithMatches),
+            "files-without-match" => self.set_output(OutputMode::FilesWithoutMatch),
+            "line-number" => self.line_number = true,
+            "after-context" => self.after_context = parse_number(name, value)?,
+            "before-context" => self.before_context = parse_number(name, value)?,
+            "context" => {
+                self.after_context = parse_number(name, value)?;
+                self.before_context = self.after_context;
+            }
+            "color" | "colour" => {
+                self.color = match value.as_deref() {
+                    Some("auto") => ColorChoice::Auto,
+                    Some("always") => ColorChoice::Always,
+                    Some("never") => ColorChoice::Never,
+                    _ => return Err(ConfigError::InvalidColor(value.unwrap_or_default())),
+                }
+            }
+            "json" => self.json = true,
+            "replace" => self.replace = value,
+            "in-place" => self.in_place = true,
+            "dry-run" => self.dry_run = true,
+            "watch" => self.watch = true,
+            "index" => self.index = true,
+            "threads" => self.threads = parse_number(name, value)?,
+            "mmap" => self.mmap = MmapChoice::Always,
+            "no-mmap" => self.mmap = MmapChoice::Never,
+            "type" => self.types.extend(value),
+            "type-not" => self.types_not.extend(value),
+            "glob" => self.globs.extend(value),
+            // Already dealt with by `new`.
+            "no-config" => {}
+            _ if name
+                .strip_prefix("no-")
+                .is_some_and(|flag| NEGATABLE.contains(&flag)) =>
+            {
+                self.negate(&name["no-".len()..])
+            }
+            // Directories are always searched recursively; accepted for
+            // muscle memory from `grep -r`.
+            "recursive" => {}
+            "help" => return Err(ConfigError::Help),
+            "version" => return Err(ConfigError::Version),
+            _ => return Err(ConfigError::UnknownFlag(format!("--{}", name))),
+        }
+        Ok(())
+    }
+
+    // Undoes one of the `NEGATABLE` flags.
+    fn negate(&mut self, name: &str) {
+        match name {
+            "extended-regexp" => self.regex = false,
+            "query" => self.query = false,
+            "ignore-case" => self.case_sensitive = true,
+            "word-regexp" => self.word_regexp = false,
+            "line-regexp" => self.line_regexp = false,
+            "invert-match" => self.invert_match = false,
+            "multiline" => self.multiline = false,
+            "count" => self.unset_output(OutputMode::Count),
+            "files-with-matches" => self.unset_output(OutputMode::FilesWithMatches),
+            "files-without-match" => self.unset_output(OutputMode::FilesWithoutMatch),
+            "only-matching" => self.unset_output(OutputMode::OnlyMatching),
+            "line-number" => self.line_number = false,
+            "json" => self.json = false,
+            "watch" => self.watch = false,
+            "index" => self.index = false,
+            _ => unreachable!("--no-{} isn't in NEGATABLE", name),
+        }
+    }
+
+    fn unset_output(&mut self, output: OutputMode) {
+        if self.output == output {
+            self.output = OutputMode::Lines;
+        }
+    }
+
+    fn set_output(&mut self, output: OutputMode) {
+        if output.rank() >= self.output.rank() {
+            self.output = output;
+        }
+    }
+}
+
+// `--record-separator`'s escapes, for separators that are awkward to type.
+fn unescape(value: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some('\\') | None => '\\',
+                // Anything else is left as it was.
+                Some(other) => {
+                    bytes.push(b'\\');
+                    other
+                }
+            },
+            c => c,
+        };
+        let mut buf = [0; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    bytes
+}
+
+fn parse_number(name: &str, value: Option<String>) -> Result<usize, ConfigError> {
+    let value = value.ok_or_else(|| ConfigError::MissingValue(format!("--{}", name)))?;
+    value
+        .parse()
+        .map_err(|_| ConfigError::InvalidNumber(format!("--{}", name), value))
+}
+
+// Whether `--no-config` is among the flags in `args`. It isn't if it's
+// the value of another option, as in `-e --no-config`, or after `--`.
+fn has_no_config(args: &[String]) -> bool {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            return false;
+        } else if arg == "--no-config" {
+            return true;
+        } else if let Some(long) = arg.strip_prefix("--") {
+            if TAKES_VALUE.contains(&long) {
+                args.next();
+            }
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            // As in `parse_args`: a short flag that takes a value ends the
+            // bundle, and only takes the next argument if it's last.
+            let flags: Vec<char> = arg[1..].chars().collect();
+            for (i, &flag) in flags.iter().enumerate() {
+                let takes_value = SHORT_FLAGS
+                    .iter()
+                    .any(|&(short, name)| short == flag && TAKES_VALUE.contains(&name));
+                if takes_value {
+                    if i + 1 == flags.len() {
+                        args.next();
+                    }
+                    break;
+                }
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Without a config file, so the user's own can't change the results.
+    fn parse(args: &[&str]) -> Result<Config, ConfigError> {
+        let args = args.iter().map(|&arg| arg.to_string()).collect();
+        Config::with_file(args, None)
+    }
+
+    #[test]
+    fn positional_arguments() {
+        let config = parse(&["to", "poem.txt", "other.txt"]).unwrap();
+        assert_eq!(vec!["to"], config.queries);
+        assert_eq!(vec!["poem.txt", "other.txt"], config.paths);
+
+        // No paths means stdin.
+        assert!(parse(&["to"]).unwrap().paths.is_empty());
+
+        let config = parse(&["index", "src", "tests"]).unwrap();
+        assert!(config.build_index);
+        assert_eq!(vec!["src", "tests"], config.paths);
+        assert_eq!(Err(ConfigError::MissingDirectory), parse(&["index"]));
+        // Only the first argument can be the command.
+        let config = parse(&["-n", "index", "src"]).unwrap();
+        assert!(!config.build_index);
+        assert_eq!(vec!["index"], config.queries);
+        for args in [&["-e", "index", "src"], &["--", "index", "src"]] {
+            let config = parse(args).unwrap();
+            assert!(!config.build_index);
+            assert_eq!(vec!["index"], config.queries);
+            assert_eq!(vec!["src"], config.paths);
+        }
+    }
+
+    #[test]
+    fn short_and_long_flags() {
+        let config = parse(&["-inv", "-A3", "--before-context=2", "-c", "to", "poem.txt"]).unwrap();
+        assert!(!config.case_sensitive);
+        assert!(config.line_number);
+        assert!(config.invert_match);
+        assert_eq!(OutputMode::Count, config.output);
+        assert_eq!(3, config.after_context);
+        assert_eq!(2, config.before_context);
+        assert_eq!(0, config.threads);
+        assert_eq!(4, parse(&["-j4", "to"]).unwrap().threads);
+        let config = parse(&["-wx", "to"]).unwrap();
+        assert!(config.word_regexp && config.line_regexp);
+        assert_eq!(Some(2), parse(&["--fuzzy=2", "to"]).unwrap().fuzzy);
+
+        let config = parse(&["-l", "-C", "1", "-e", "-dash", "--", "-file"]).unwrap();
+        assert_eq!(OutputMode::FilesWithMatches, config.output);
+        assert_eq!((1, 1), (config.before_context, config.after_context));
+        assert_eq!(vec!["-dash"], config.queries);
+        assert_eq!(vec!["-file"], config.paths);
+    }
+
+    #[test]
+    fn patterns() {
+        let config = parse(&["-e", "a", "--regexp=b", "-eb", "f"]).unwrap();
+        assert_eq!(vec!["a", "b", "b"], config.queries);
+        assert_eq!(vec!["f"], config.paths);
+
+        let dir = env::temp_dir().join(format!("minigrep-patterns-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("patterns.txt");
+        fs::write(&file, "one\ntwo\n").unwrap();
+        let config = parse(&["-e", "zero", "-f", file.to_str().unwrap(), "f"]).unwrap();
+        assert_eq!(vec!["zero", "one", "two"], config.queries);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn config_file() {
+        let file = ConfigFile {
+            flags: vec!["-i".to_string(), "--context".to_string(), "2".to_string()],
+            types: vec![("proto".to_string(), vec!["*.proto".to_string()])],
+            ..ConfigFile::default()
+        };
+        let with_file = |args: &[&str]| {
+            let args = args.iter().map(|&arg| arg.to_string()).collect();
+            Config::with_file(args, Some(file.clone()))
+        };
+        let config = with_file(&["-t", "proto", "-trust", "-T", "markdown", "to"]).unwrap();
+        assert!(!config.case_sensitive);
+        assert_eq!((2, 2), (config.before_context, config.after_context));
+        assert_eq!(vec!["proto", "rust"], config.types);
+        assert_eq!(vec!["markdown"], config.types_not);
+        // The command line has the last word.
+        let config = with_file(&["-s", "-C0", "to"]).unwrap();
+        assert!(config.case_sensitive);
+        assert_eq!((0, 0), (config.before_context, config.after_context));
+
+        assert_eq!(
+            Err(ConfigError::UnknownType("cobol".to_string())),
+            with_file(&["--type-not=cobol", "to"])
+        );
+        let file = ConfigFile {
+            flags: vec!["needle".to_string()],
+            ..ConfigFile::default()
+        };
+        assert!(matches!(
+            Config::with_file(vec!["to".to_string()], Some(file)),
+            Err(ConfigError::ConfigFile(..))
+        ));
+    }
+
+    #[test]
+    fn negated_flags() {
+        let file = ConfigFile {
+            flags: ["--json", "-n", "-i", "-v"]
+                .iter()
+                .map(|&flag| flag.to_string())
+                .collect(),
+            ..ConfigFile::default()
+        };
+        let with_file = |args: &[&str]| {
+            let args = args.iter().map(|&arg| arg.to_string()).collect();
+            Config::with_file(args, Some(file.clone()))
+        };
+        let args = ["--no-json", "--no-line-number", "--no-invert-match", "to"];
+        let config = with_file(&args).unwrap();
+        assert!(!config.json && !config.line_number && !config.invert_match);
+        assert!(!config.case_sensitive);
+        assert!(
+            with_file(&["--no-ignore-case", "to"])
+                .unwrap()
+                .case_sensitive
+        );
+        let config = parse(&["-c", "--no-count", "to"]).unwrap();
+        assert_eq!(OutputMode::Lines, config.output);
+        let config = parse(&["-l", "--no-count", "to"]).unwrap();
+        assert_eq!(OutputMode::FilesWithMatches, config.output);
+        assert_eq!(
+            Err(ConfigError::UnknownFlag("--no-replace".to_string())),
+            parse(&["--no-replace", "to"])
+        );
+    }
+
+    #[test]
+    fn no_config_only_as_a_flag() {
+        let has = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|&arg| arg.to_string()).collect();
+            has_no_config(&args)
+        };
+        assert!(has(&["-n", "--no-config", "to"]));
+        assert!(has(&["-e", "to", "--no-config"]));
+        assert!(has(&["-ento", "--no-config"]));
+        assert!(!has(&["-e", "--no-config", "f"]));
+        assert!(!has(&["-ne", "--no-config", "f"]));
+        assert!(!has(&["--regexp", "--no-config"]));
+        assert!(!has(&["to", "--", "--no-config"]));
+    }
+
+    #[test]
+    fn color_choice() {
+        assert_eq!(ColorChoice::Auto, parse(&["to"]).unwrap().color);
+        assert_eq!(ColorChoice::Auto, parse(&["--color", "to"]).unwrap().color);
+        assert_eq!(
+            ColorChoice::Always,
+            parse(&["--color=always", "to"]).unwrap().color
+        );
+        assert_eq!(
+            ColorChoice::Never,
+            parse(&["--colour=never", "to"]).unwrap().color
+        );
+        assert_eq!(
+            Err(ConfigError::InvalidColor("sometimes".to_string())),
+            parse(&["--color=sometimes", "to"])
+        );
+    }
+
+    #[test]
+    fn mmap_choice() {
+        assert_eq!(MmapChoice::Auto, parse(&["to"]).unwrap().mmap);
+        assert_eq!(MmapChoice::Always, parse(&["--mmap", "to"]).unwrap().mmap);
+        // The last one given wins.
+        assert_eq!(
+            MmapChoice::Never,
+            parse(&["--mmap", "--no-mmap", "to"]).unwrap().mmap
+        );
+    }
+
+    #[test]
+    fn records() {
+        let records = |args: &[&str]| parse(args).unwrap().records;
+        assert_eq!(Records::Lines, records(&["to"]));
+        assert_eq!(Records::Paragraphs, records(&["-p", "to"]));
+        assert_eq!(Records::Separator(vec![0]), records(&["-z", "to"]));
+        assert_eq!(
+            Records::Separator(b"--\n\t\\x\\".to_vec()),
+            records(&["--record-separator", "--\\n\\t\\x\\", "to"])
+        );
+        assert_eq!(
+            Err(ConfigError::Conflict("-U".to_string(), "-z".to_string())),
+            parse(&["-Uz", "to"])
+        );
+    }
+
+    #[test]
+    fn binary_files() {
+        let binary_files = |args: &[&str]| parse(args).unwrap().binary_files;
+        assert_eq!(BinaryFiles::Binary, binary_files(&["to"]));
+        assert_eq!(BinaryFiles::Text, binary_files(&["-a", "to"]));
+        assert_eq!(BinaryFiles::WithoutMatch, binary_files(&["-aI", "to"]));
+        assert_eq!(
+            BinaryFiles::Text,
+            binary_files(&["--binary-files=text", "to"])
+        );
+        assert_eq!(
+            Err(ConfigError::InvalidBinaryFiles("maybe".to_string())),
+            parse(&["--binary-files", "maybe", "to"])
+        );
+    }
+
+    #[test]
+    fn output_mode_precedence() {
+        assert_eq!(OutputMode::Lines, parse(&["to"]).unwrap().output);
+        assert_eq!(
+            OutputMode::Count,
+            parse(&["-c", "-o", "to"]).unwrap().output
+        );
+        assert_eq!(
+            OutputMode::FilesWithMatches,
+            parse(&["-l", "-c", "to"]).unwrap().output
+        );
+        assert_eq!(
+            OutputMode::FilesWithoutMatch,
+            parse(&["-lL", "to"]).unwrap().output
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Err(ConfigError::MissingPattern), parse(&[]));
+        assert_eq!(
+            Err(ConfigError::UnknownFlag("-q".to_string())),
+            parse(&["-q", "to", "f"])
+        );
+        assert_eq!(
+            Err(ConfigError::MissingValue("-A".to_string())),
+            parse(&["to", "f", "-A"])
+        );
+        assert_eq!(
+            Err(ConfigError::InvalidNumber(
+                "--context".to_string(),
+                "x".to_string()
+            )),
+            parse(&["-C", "x", "to", "f"])
+        );
+        assert_eq!(
+            Err(ConfigError::UnexpectedValue("--count".to_string())),
+            parse(&["--count=1", "to", "f"])
+        );
+        assert!(matches!(
+            parse(&["-f", "does/not/exist", "f"]),
+            Err(ConfigError::PatternFile(..))
+        ));
+        assert_eq!(
+            Err(ConfigError::Conflict(
+                "--json".to_string(),
+                "-c".to_string()
+            )),
+            parse(&["--json", "-c", "to"])
+        );
+        assert_eq!(
+            Err(ConfigError::Requires(
+                "--in-place".to_string(),
+                "--replace".to_string()
+            )),
+            parse(&["--in-place", "to", "f"])
+        );
+        assert_eq!(
+            Err(ConfigError::Conflict(
+                "--dry-run".to_string(),
+                "-p".to_string()
+            )),
+            parse(&["--dry-run", "-p", "--replace=x", "to", "f"])
+        );
+        assert_eq!(
+            Err(ConfigError::Conflict(
+                "--replace".to_string(),
+                "-v".to_string()
+            )),
+            parse(&["--replace=x", "-v", "to", "f"])
+        );
+        assert_eq!(
+            Err(ConfigError::Conflict(
+                "--fuzzy".to_string(),
+                "-w".to_string()
+            )),
+            parse(&["--fuzzy", "1", "-w", "to"])
+        );
+        assert_eq!(
+            Err(ConfigError::Conflict(
+                "--watch".to_string(),
+                "-l".to_string()
+            )),
+            parse(&["--watch", "-l", "to", "f"])
+        );
+        assert_eq!(
+            Err(ConfigError::Conflict(
+                "--watch".to_string(),
+                "--json".to_string()
+            )),
+            parse(&["--watch", "--json", "to", "f"])
+        );
+        assert_eq!(
+            Err(ConfigError::Conflict(
+                "--query".to_string(),
+                "-U".to_string()
+            )),
+            parse(&["-QU", "a AND b", "f"])
+        );
+        assert_eq!(Err(ConfigError::Help), parse(&["--help"]));
+        assert_eq!(Err(ConfigError::Version), parse(&["-V"]));
+    }
+}
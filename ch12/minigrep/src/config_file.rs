@@ -0,0 +1,177 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::printer::Colors;
+use crate::toml::{self, Value};
+use crate::ConfigError;
+
+// Per-user defaults, read from `$MINIGREP_CONFIG` if it's set, otherwise
+// from `~/.config/minigrep/config.toml` if that exists. For example:
+//
+//   # Flags to use on every search, as if typed before the command line's.
+//   flags = ["--ignore-case", "--line-number"]
+//   # Skipped when searching a directory, like a global `.gitignore`.
+//   ignore = ["*.min.js", "vendor/"]
+//
+//   # Colors, as ANSI SGR parameters.
+//   [colors]
+//   matched = "1;33"
+//   path = "34"
+//
+//   # Named sets of file globs for `--type NAME`, on top of the built-in
+//   # ones.
+//   [types]
+//   rust = ["*.rs"]
+//   web = ["*.html", "*.css", "*.js"]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ConfigFile {
+    // Where the file was read from, for error messages.
+    pub path: PathBuf,
+    pub flags: Vec<String>,
+    pub colors: Colors,
+    pub ignore: Vec<String>,
+    pub types: Vec<(String, Vec<String>)>,
+}
+
+impl ConfigFile {
+    // The file to read, if any. An empty `MINIGREP_CONFIG` turns the config
+    // file off.
+    pub fn location() -> Option<PathBuf> {
+        match env::var_os("MINIGREP_CONFIG") {
+            Some(path) if path.is_empty() => None,
+            Some(path) => Some(PathBuf::from(path)),
+            None => {
+                let home = env::var_os("HOME")?;
+                Some(Path::new(&home).join(".config/minigrep/config.toml"))
+            }
+        }
+    }
+
+    // Reads the config file. There doesn't have to be one at the default
+    // location, but a file named by `MINIGREP_CONFIG` must exist.
+    pub fn load() -> Result<Option<ConfigFile>, ConfigError> {
+        let path = match ConfigFile::location() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let error = |reason: String| ConfigError::ConfigFile(path.display().to_string(), reason);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e)
+                if e.kind() == io::ErrorKind::NotFound
+                    && env::var_os("MINIGREP_CONFIG").is_none() =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(error(e.to_string())),
+        };
+        let mut file = ConfigFile::parse(&text).map_err(error)?;
+        file.path = path;
+        Ok(Some(file))
+    }
+
+    pub fn parse(text: &str) -> Result<ConfigFile, String> {
+        let entries = toml::parse(text).map_err(|e| e.to_string())?;
+        let mut file = ConfigFile::default();
+        for entry in entries {
+            let error = |message: String| format!("line {}: {}", entry.line, message);
+            let expected = |what: &str| {
+                error(format!(
+                    "'{}' should be {}, not {}",
+                    entry.key,
+                    what,
+                    entry.value.kind()
+                ))
+            };
+            match (entry.table.as_str(), entry.key.as_str()) {
+                ("", "flags") => {
+                    file.flags =
+                        strings(&entry.value).ok_or_else(|| expected("an array of strings"))?
+                }
+                ("", "ignore") => {
+                    file.ignore =
+                        strings(&entry.value).ok_or_else(|| expected("an array of strings"))?
+                }
+                ("colors", key) => {
+                    let color = match &entry.value {
+                        Value::String(s) => s.clone(),
+                        _ => return Err(expected("a string")),
+                    };
+                    let colors = &mut file.colors;
+                    let field = match key {
+                        "matched" => &mut colors.matched,
+                        "path" => &mut colors.path,
+                        "line_number" => &mut colors.line_number,
+                        "separator" => &mut colors.separator,
+                        _ => return Err(error(format!("unknown color '{}'", key))),
+                    };
+                    *field = color;
+                }
+                ("types", name) => {
+                    let globs =
+                        strings(&entry.value).ok_or_else(|| expected("an array of strings"))?;
+                    file.types.push((name.to_string(), globs));
+                }
+                ("", key) => return Err(error(format!("unknown setting '{}'", key))),
+                (table, _) => return Err(error(format!("unknown table '[{}]'", table))),
+            }
+        }
+        Ok(file)
+    }
+}
+
+fn strings(value: &Value) -> Option<Vec<String>> {
+    match value {
+        Value::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                Value::String(s) => Some(s.clone()),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings() {
+        let file = ConfigFile::parse(
+            "flags = ['-i', '--context=1']\n\
+             ignore = ['*.lock']\n\
+             [colors]\n\
+             matched = '1;33'\n\
+             [types]\n\
+             rust = ['*.rs']\n",
+        )
+        .unwrap();
+        assert_eq!(vec!["-i", "--context=1"], file.flags);
+        assert_eq!(vec!["*.lock"], file.ignore);
+        assert_eq!("1;33", file.colors.matched);
+        assert_eq!("35", file.colors.path);
+        assert_eq!(
+            vec![("rust".to_string(), vec!["*.rs".to_string()])],
+            file.types
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = |text: &str| ConfigFile::parse(text).unwrap_err();
+        assert_eq!(
+            "line 1: 'flags' should be an array of strings, not a string",
+            error("flags = '-i'")
+        );
+        assert_eq!("line 2: unknown setting 'colour'", error("\ncolour = true"));
+        assert_eq!(
+            "line 2: unknown color 'match'",
+            error("[colors]\nmatch = '31'")
+        );
+        assert_eq!("line 1: expected a key", error("= 1"));
+    }
+}
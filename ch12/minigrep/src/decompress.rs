@@ -0,0 +1,133 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use crate::gzip::{self, GzDecoder};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Gzip,
+    Bzip2,
+    Xz,
+}
+
+impl Format {
+    // Recognizes a compressed file by its first few bytes, whatever it's
+    // called.
+    pub fn detect(start: &[u8]) -> Option<Format> {
+        if start.starts_with(&gzip::MAGIC) {
+            Some(Format::Gzip)
+        } else if start.starts_with(b"BZh") && matches!(start.get(3), Some(b'1'..=b'9')) {
+            // The digit is the block size, in hundreds of kilobytes.
+            Some(Format::Bzip2)
+        } else if start.starts_with(b"\xfd7zXZ\x00") {
+            Some(Format::Xz)
+        } else {
+            None
+        }
+    }
+
+    // The program that decompresses formats we have no decoder for.
+    fn program(self) -> &'static str {
+        match self {
+            Format::Gzip => "gzip",
+            Format::Bzip2 => "bzip2",
+            Format::Xz => "xz",
+        }
+    }
+}
+
+// Opens the file at `path` for reading, decompressing it on the fly if it's
+// compressed. gzip is decoded here; bzip2 and xz are handed to the `bzip2`
+// and `xz` programs, which have to be installed.
+pub fn open(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    // The first buffer-full is plenty to recognize a format by.
+    let format = Format::detect(reader.fill_buf()?);
+    Ok(match format {
+        None => Box::new(reader),
+        Some(Format::Gzip) => Box::new(BufReader::new(GzDecoder::new(reader))),
+        Some(format) => Box::new(BufReader::new(ChildReader::spawn(format, path)?)),
+    })
+}
+
+// The output of a decompressor run as a child process. Reaching the end of
+// it waits for the process, so that a failure is reported rather than
+// looking like the end of the file.
+struct ChildReader {
+    child: Child,
+    stdout: ChildStdout,
+    program: &'static str,
+}
+
+impl ChildReader {
+    fn spawn(format: Format, path: &Path) -> io::Result<ChildReader> {
+        let program = format.program();
+        let mut child = Command::new(program)
+            .arg("-dc")
+            .arg("--")
+            .arg(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("can't run {} to decompress this file: {}", program, e),
+                )
+            })?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(ChildReader {
+            child,
+            stdout,
+            program,
+        })
+    }
+}
+
+impl Read for ChildReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} failed to decompress this file ({})",
+                        self.program, status
+                    ),
+                ));
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for ChildReader {
+    // Stopping early, as `-l` does, mustn't leave the process behind.
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_formats() {
+        assert_eq!(Some(Format::Gzip), Format::detect(&[0x1f, 0x8b, 8, 0]));
+        assert_eq!(Some(Format::Bzip2), Format::detect(b"BZh91AY&SY"));
+        assert_eq!(Some(Format::Xz), Format::detect(b"\xfd7zXZ\x00\x00"));
+        assert_eq!(None, Format::detect(b"BZ"));
+        assert_eq!(None, Format::detect(b"BZh"));
+        assert_eq!(None, Format::detect(b"BZhello"));
+        assert_eq!(None, Format::detect(b"plain text"));
+    }
+}
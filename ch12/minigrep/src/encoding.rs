@@ -0,0 +1,240 @@
+use std::borrow::Cow;
+use std::io::{self, BufRead, Read};
+use std::str;
+
+pub(crate) const UTF8_BOM: [u8; 3] = [0xef, 0xbb, 0xbf];
+const UTF16LE_BOM: [u8; 2] = [0xff, 0xfe];
+const UTF16BE_BOM: [u8; 2] = [0xfe, 0xff];
+
+// Looks for a byte order mark at the start of `reader`. UTF-16 text is
+// turned into UTF-8 as it's read, so the rest of the search only ever sees
+// UTF-8 (or bytes that aren't text at all; see `decode_line`). A UTF-8 BOM
+// is just dropped, so it can't end up glued to the first line.
+pub fn decode<'a, R: BufRead + 'a>(mut reader: R) -> io::Result<Box<dyn BufRead + 'a>> {
+    let start = reader.fill_buf()?;
+    if start.starts_with(&UTF8_BOM) {
+        reader.consume(UTF8_BOM.len());
+        Ok(Box::new(reader))
+    } else if start.starts_with(&UTF16LE_BOM) || start.starts_with(&UTF16BE_BOM) {
+        let big_endian = start.starts_with(&UTF16BE_BOM);
+        reader.consume(2);
+        Ok(Box::new(Utf16Reader::new(reader, big_endian)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+// A line of text from its bytes. Each byte that isn't part of valid UTF-8
+// is taken to be Latin-1, where every byte is a char, so that old files in
+// a legacy encoding can still be searched rather than being an error. The
+// valid UTF-8 around it is kept as it is.
+pub fn decode_line(bytes: &[u8]) -> Cow<'_, str> {
+    let mut rest = match str::from_utf8(bytes) {
+        Ok(text) => return Cow::Borrowed(text),
+        Err(_) => bytes,
+    };
+    let mut text = String::with_capacity(bytes.len() + bytes.len() / 2);
+    loop {
+        match str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                return Cow::Owned(text);
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                // Checked just above, so this can't fail.
+                text.push_str(str::from_utf8(valid).unwrap());
+                // A sequence cut short by the end of the line is as many
+                // Latin-1 chars as it has bytes.
+                let invalid = e.error_len().unwrap_or(after.len());
+                text.extend(after[..invalid].iter().map(|&b| b as char));
+                rest = &after[invalid..];
+            }
+        }
+    }
+}
+
+// Where `offset`, a byte offset into `decode_line(bytes)`, came from in
+// `bytes`. Valid UTF-8 is the same in both; each Latin-1 byte became a
+// two-byte char.
+pub fn raw_offset(bytes: &[u8], offset: usize) -> usize {
+    let (mut raw, mut text) = (0, 0);
+    loop {
+        let rest = &bytes[raw..];
+        let (valid, invalid) = match str::from_utf8(rest) {
+            Ok(_) => (rest.len(), 0),
+            Err(e) => {
+                let invalid = e.error_len().unwrap_or(rest.len() - e.valid_up_to());
+                (e.valid_up_to(), invalid)
+            }
+        };
+        if offset <= text + valid || invalid == 0 {
+            return raw + (offset - text).min(valid);
+        }
+        raw += valid;
+        text += valid;
+        if offset < text + 2 * invalid {
+            return raw + (offset - text) / 2;
+        }
+        raw += invalid;
+        text += 2 * invalid;
+    }
+}
+
+// UTF-16 in, UTF-8 out. Whatever the underlying reader has buffered is
+// decoded in one go, so a slow pipe doesn't have to fill a whole buffer
+// before anything comes out. Unpaired surrogates and a stray odd byte at
+// the end become U+FFFD, like `String::from_utf16_lossy`.
+struct Utf16Reader<R> {
+    inner: R,
+    big_endian: bool,
+    // Decoded UTF-8 not yet read, from `pos` on.
+    out: Vec<u8>,
+    pos: usize,
+    // The first byte of a code unit split between two reads.
+    odd_byte: Option<u8>,
+    // A high surrogate waiting for the low one that completes it.
+    high: Option<u16>,
+}
+
+impl<R: BufRead> Utf16Reader<R> {
+    fn new(inner: R, big_endian: bool) -> Utf16Reader<R> {
+        Utf16Reader {
+            inner,
+            big_endian,
+            out: Vec::new(),
+            pos: 0,
+            odd_byte: None,
+            high: None,
+        }
+    }
+
+    fn unit(&self, a: u8, b: u8) -> u16 {
+        if self.big_endian {
+            u16::from_be_bytes([a, b])
+        } else {
+            u16::from_le_bytes([a, b])
+        }
+    }
+
+    fn push_char(&mut self, c: char) {
+        let mut buf = [0; 4];
+        self.out
+            .extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+
+    fn push_unit(&mut self, unit: u16) {
+        if let Some(high) = self.high.take() {
+            if (0xdc00..0xe000).contains(&unit) {
+                let c = 0x10000 + ((high as u32 - 0xd800) << 10) + (unit as u32 - 0xdc00);
+                self.push_char(char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER));
+                return;
+            }
+            self.push_char(char::REPLACEMENT_CHARACTER);
+        }
+        if (0xd800..0xdc00).contains(&unit) {
+            self.high = Some(unit);
+        } else {
+            // A low surrogate on its own isn't a char.
+            let c = char::from_u32(unit as u32).unwrap_or(char::REPLACEMENT_CHARACTER);
+            self.push_char(c);
+        }
+    }
+}
+
+impl<R: BufRead> BufRead for Utf16Reader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.out.len() {
+            self.out.clear();
+            self.pos = 0;
+            let chunk = self.inner.fill_buf()?.to_vec();
+            if chunk.is_empty() {
+                if self.odd_byte.take().is_some() || self.high.take().is_some() {
+                    self.push_char(char::REPLACEMENT_CHARACTER);
+                    continue;
+                }
+                break;
+            }
+            self.inner.consume(chunk.len());
+            let mut bytes = chunk.as_slice();
+            if let Some(first) = self.odd_byte.take() {
+                self.push_unit(self.unit(first, bytes[0]));
+                bytes = &bytes[1..];
+            }
+            let mut pairs = bytes.chunks_exact(2);
+            for pair in &mut pairs {
+                self.push_unit(self.unit(pair[0], pair[1]));
+            }
+            self.odd_byte = pairs.remainder().first().copied();
+        }
+        Ok(&self.out[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.out.len());
+    }
+}
+
+impl<R: BufRead> Read for Utf16Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    fn decoded(bytes: &[u8]) -> String {
+        let mut text = String::new();
+        // A tiny buffer, so code units and surrogate pairs get split.
+        decode(BufReader::with_capacity(3, bytes))
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn byte_order_marks() {
+        assert_eq!("héllo", decoded(b"\xef\xbb\xbfh\xc3\xa9llo"));
+        let le: Vec<u8> = [0xfeff, 0x68, 0xe9, 0xd83e, 0xdd80, 0x0a]
+            .iter()
+            .flat_map(|u: &u16| u.to_le_bytes())
+            .collect();
+        assert_eq!("hé🦀\n", decoded(&le));
+        let be: Vec<u8> = [0xfeff, 0x61, 0xdd80, 0x62]
+            .iter()
+            .flat_map(|u: &u16| u.to_be_bytes())
+            .collect();
+        assert_eq!("a\u{fffd}b", decoded(&be));
+        assert_eq!("a\u{fffd}", decoded(b"\xff\xfea\x00\x62"));
+    }
+
+    #[test]
+    fn latin1_lines() {
+        assert_eq!("caf\u{e9}", decode_line(b"caf\xc3\xa9"));
+        assert_eq!("caf\u{e9}", decode_line(b"caf\xe9"));
+        assert_eq!(
+            "caf\u{e9} caf\u{e9} \u{e2}\u{82}",
+            decode_line(b"caf\xc3\xa9 caf\xe9 \xe2\x82")
+        );
+    }
+
+    #[test]
+    fn raw_offsets() {
+        let bytes = b"caf\xc3\xa9 caf\xe9 x \xe2\x82";
+        let text = decode_line(bytes);
+        for (needle, start, end) in [("é ", 3, 6), ("é x", 9, 12), ("\u{82}", 14, 15)] {
+            let i = text.find(needle).unwrap();
+            assert_eq!(start, raw_offset(bytes, i));
+            assert_eq!(end, raw_offset(bytes, i + needle.len()));
+        }
+        assert_eq!(2, raw_offset(b"ab", 2));
+    }
+}
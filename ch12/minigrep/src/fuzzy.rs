@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::casefold::fold;
+use crate::Matcher;
+
+// The pattern's bits have to fit in one machine word.
+pub const MAX_PATTERN_LEN: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternTooLong(pub usize);
+
+impl fmt::Display for PatternTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "fuzzy patterns can be at most {} chars long, got {}",
+            MAX_PATTERN_LEN, self.0
+        )
+    }
+}
+
+impl Error for PatternTooLong {}
+
+// Approximate matching, like agrep: finds text within `max_edits` edits
+// (inserted, deleted or substituted chars) of the pattern.
+//
+// The search is Myers' bit-parallel algorithm. Conceptually it fills in the
+// classic edit distance table, with a row per pattern char and a column per
+// text char, where the top row is all zeros so a match may start anywhere.
+// Adjacent cells differ by at most one, so a column can be stored as two
+// bit vectors, "went up by one" and "went down by one", and the next column
+// computed from them with a handful of word operations. The bottom cell of
+// each column, the best distance of any match ending at that char, is
+// tracked in `score`.
+pub struct Fuzzy {
+    pattern: Vec<char>,
+    max_edits: usize,
+    case_insensitive: bool,
+    // For each char, the positions in the pattern where it occurs.
+    ascii_peq: [u64; 128],
+    peq: HashMap<char, u64>,
+}
+
+// The state between columns: the vertical deltas and the bottom cell.
+#[derive(Clone, Copy)]
+struct Column {
+    pv: u64,
+    mv: u64,
+    score: usize,
+}
+
+impl Fuzzy {
+    pub fn new(pattern: &str, max_edits: usize) -> Result<Fuzzy, PatternTooLong> {
+        Fuzzy::build(pattern, max_edits, false)
+    }
+
+    // Compares chars with Unicode simple case folding.
+    pub fn case_insensitive(pattern: &str, max_edits: usize) -> Result<Fuzzy, PatternTooLong> {
+        Fuzzy::build(pattern, max_edits, true)
+    }
+
+    fn build(
+        pattern: &str,
+        max_edits: usize,
+        case_insensitive: bool,
+    ) -> Result<Fuzzy, PatternTooLong> {
+        let pattern: Vec<char> = if case_insensitive {
+            pattern.chars().map(fold).collect()
+        } else {
+            pattern.chars().collect()
+        };
+        if pattern.len() > MAX_PATTERN_LEN {
+            return Err(PatternTooLong(pattern.len()));
+        }
+        let mut ascii_peq = [0; 128];
+        let mut peq = HashMap::new();
+        for (i, &c) in pattern.iter().enumerate() {
+            if c.is_ascii() {
+                ascii_peq[c as usize] |= 1 << i;
+            } else {
+                *peq.entry(c).or_insert(0) |= 1 << i;
+            }
+        }
+        Ok(Fuzzy {
+            pattern,
+            max_edits,
+            case_insensitive,
+            ascii_peq,
+            peq,
+        })
+    }
+
+    fn start(&self) -> Column {
+        Column {
+            pv: !0,
+            mv: 0,
+            score: self.pattern.len(),
+        }
+    }
+
+    // Moves on to the column for text char `c`.
+    fn step(&self, col: Column, c: char) -> Column {
+        let c = if self.case_insensitive { fold(c) } else { c };
+        let eq = if c.is_ascii() {
+            self.ascii_peq[c as usize]
+        } else {
+            self.peq.get(&c).copied().unwrap_or(0)
+        };
+        let last = 1 << (self.pattern.len() - 1);
+        let xv = eq | col.mv;
+        let xh = ((eq & col.pv).wrapping_add(col.pv) ^ col.pv) | eq;
+        let ph = col.mv | !(xh | col.pv);
+        let mh = col.pv & xh;
+        let mut score = col.score;
+        if ph & last != 0 {
+            score += 1;
+        } else if mh & last != 0 {
+            score -= 1;
+        }
+        // No carry into the first row: the top row is all zeros, so a
+        // match can start at any char.
+        let ph = ph << 1;
+        let mh = mh << 1;
+        Column {
+            pv: mh | !(xv | ph),
+            mv: ph & xv,
+            score,
+        }
+    }
+
+    // The first match at or after `start` within `max_edits`, with the
+    // number of edits it needs. Among overlapping candidates it prefers the
+    // one with the fewest edits, so "abcd" matches all of "abcd" rather
+    // than stopping at "abc" with one edit.
+    pub fn find_with_distance(
+        &self,
+        haystack: &str,
+        start: usize,
+    ) -> Option<(usize, usize, usize)> {
+        if self.pattern.is_empty() {
+            return Some((start, start, 0));
+        }
+        let mut col = self.start();
+        let mut best: Option<(usize, usize)> = None;
+        for (i, c) in haystack[start..].char_indices() {
+            col = self.step(col, c);
+            let end = start + i + c.len_utf8();
+            match best {
+                Some((_, score)) if col.score >= score => break,
+                _ if col.score <= self.max_edits => best = Some((end, col.score)),
+                _ => {}
+            }
+        }
+        let (end, score) = best?;
+        Some((self.match_start(haystack, start, end, score), end, score))
+    }
+
+    // Where a match ending at `end` with `score` edits starts. Myers only
+    // tracks where matches end, so this goes back over the text with the
+    // full table, for the pattern and text both reversed. The match can't
+    // be longer than the pattern plus `score` chars.
+    fn match_start(&self, haystack: &str, start: usize, end: usize, score: usize) -> usize {
+        let text: Vec<(usize, char)> = haystack[start..end]
+            .char_indices()
+            .rev()
+            .take(self.pattern.len() + score)
+            .map(|(i, c)| (start + i, if self.case_insensitive { fold(c) } else { c }))
+            .collect();
+        // `row[j]`: edits to turn the reversed pattern so far into the last
+        // `j` chars of the text.
+        let mut row: Vec<usize> = (0..=text.len()).collect();
+        for (i, &p) in self.pattern.iter().rev().enumerate() {
+            let mut diagonal = row[0];
+            row[0] = i + 1;
+            for j in 1..=text.len() {
+                let cost = if text[j - 1].1 == p { 0 } else { 1 };
+                let cell = (diagonal + cost).min(row[j] + 1).min(row[j - 1] + 1);
+                diagonal = row[j];
+                row[j] = cell;
+            }
+        }
+        // The longest stretch of text that still only needs `score` edits.
+        (1..=text.len())
+            .rev()
+            .find(|&j| row[j] == score)
+            .map_or(end, |j| text[j - 1].0)
+    }
+}
+
+impl Matcher for Fuzzy {
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        self.find_with_distance(haystack, start)
+            .map(|(s, e, _)| (s, e))
+    }
+
+    // Any match will do, however many edits it needs.
+    fn is_match(&self, haystack: &str) -> bool {
+        if self.pattern.is_empty() {
+            return true;
+        }
+        let mut col = self.start();
+        haystack.chars().any(|c| {
+            col = self.step(col, c);
+            col.score <= self.max_edits
+        })
+    }
+
+    // The fewest edits of any match in `haystack`.
+    fn edit_distance(&self, haystack: &str) -> Option<usize> {
+        if self.pattern.is_empty() {
+            return Some(0);
+        }
+        let mut col = self.start();
+        let mut best = None;
+        for c in haystack.chars() {
+            col = self.step(col, c);
+            if col.score <= self.max_edits && best.is_none_or(|best| col.score < best) {
+                best = Some(col.score);
+                if col.score == 0 {
+                    break;
+                }
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances() {
+        let m = Fuzzy::new("survey", 2).unwrap();
+        assert_eq!(Some(0), m.edit_distance("a survey of"));
+        assert_eq!(Some(1), m.edit_distance("a surwey of"));
+        assert_eq!(Some(2), m.edit_distance("a surgery of"));
+        assert_eq!(None, m.edit_distance("a sunny day"));
+        assert_eq!(Some(1), m.edit_distance("survy"));
+        assert!(m.is_match("xsurvexy"));
+
+        let m = Fuzzy::case_insensitive("Straße", 1).unwrap();
+        assert_eq!(Some(0), m.edit_distance("STRASSE straße"));
+        assert_eq!(Some(1), m.edit_distance("strase"));
+
+        assert!(Fuzzy::new(&"x".repeat(65), 1).is_err());
+    }
+
+    #[test]
+    fn spans() {
+        let m = Fuzzy::new("abcd", 1).unwrap();
+        // Not "abc" with one edit: carries on while the distance falls.
+        assert_eq!(Some((1, 5, 0)), m.find_with_distance("xabcdx", 0));
+        assert_eq!(Some((2, 6, 1)), m.find_with_distance("x abXd", 0));
+        assert_eq!(vec![(0, 3), (4, 8)], m.find_all("abd abcd"));
+        // Spans are byte offsets into the original text.
+        let m = Fuzzy::new("hello", 1).unwrap();
+        assert_eq!(Some((3, 9, 1)), m.find_with_distance("ô héllo", 0));
+    }
+}
@@ -0,0 +1,276 @@
+// Shell-style wildcards, matched against `/`-separated paths:
+//
+//   `?`       any char but `/`
+//   `*`       any run of chars without a `/`
+//   `**/`     any number of leading directories, including none
+//   `/**`     everything inside
+//   `[a-z]`   a char in the class; `[!a-z]` or `[^a-z]` one that isn't
+//   `\*`      a literal `*`
+//
+// `**` anywhere else (`a**`, `**b`) is the same as `*`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glob {
+    pattern: Vec<char>,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Glob {
+        Glob {
+            pattern: pattern.chars().collect(),
+        }
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        let path: Vec<char> = path.chars().collect();
+        self.is_match_chars(&path)
+    }
+
+    // For callers matching many globs against the same path, which only
+    // need to split it into chars once.
+    pub fn is_match_chars(&self, path: &[char]) -> bool {
+        glob_match(&self.pattern, path)
+    }
+}
+
+// Walks the pattern and the text together. When they stop matching, the
+// last `*` takes one more char of the text and everything after it is tried
+// again; once that `*` can't (it would have to take a `/`), the last `**/`
+// takes one more directory instead. Going back no further than that keeps
+// it to a few passes over the text, however many wildcards there are.
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where to start again from: the pattern just after the wildcard, and
+    // the text it's taken up to so far.
+    let mut star: Option<(usize, usize)> = None;
+    let mut globstar: Option<(usize, usize)> = None;
+    while p < pattern.len() || t < text.len() {
+        let next = text.get(t);
+        match pattern.get(p) {
+            Some('*') => {
+                let stars = pattern[p..].iter().take_while(|&&c| c == '*').count();
+                // Only a whole path component of `**` is special, as in
+                // gitignore; `a**` is just `a*`.
+                let component = p == 0 || pattern[p - 1] == '/';
+                p += stars;
+                if stars > 1 && component {
+                    match pattern.get(p) {
+                        // `**` at the end matches everything that's left.
+                        None => return true,
+                        // `**/` matches zero or more whole directories.
+                        Some('/') => {
+                            p += 1;
+                            globstar = Some((p, t));
+                            star = None;
+                            continue;
+                        }
+                        Some(_) => {}
+                    }
+                }
+                star = Some((p, t));
+                continue;
+            }
+            Some('?') if next.is_some_and(|&c| c != '/') => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            Some('[') => match class_match(&pattern[p + 1..], next) {
+                Some((true, len)) => {
+                    p += 1 + len;
+                    t += 1;
+                    continue;
+                }
+                // An unterminated `[` is just a literal bracket.
+                None if next == Some(&'[') => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                _ => {}
+            },
+            Some('\\') if p + 1 < pattern.len() && next == Some(&pattern[p + 1]) => {
+                p += 2;
+                t += 1;
+                continue;
+            }
+            Some('\\') if p + 1 < pattern.len() => {}
+            Some(&c) if c != '?' && next == Some(&c) => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            _ => {}
+        }
+        if let Some((star_p, star_t)) = star {
+            if text.get(star_t).is_some_and(|&c| c != '/') {
+                star = Some((star_p, star_t + 1));
+                p = star_p;
+                t = star_t + 1;
+                continue;
+            }
+        }
+        if let Some((globstar_p, globstar_t)) = globstar {
+            if let Some(i) = text[globstar_t..].iter().position(|&c| c == '/') {
+                t = globstar_t + i + 1;
+                p = globstar_p;
+                globstar = Some((p, t));
+                star = None;
+                continue;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+// Matches the inside of a `[...]` class (without the opening bracket).
+// Returns whether `c` is in the class and how many pattern chars the class
+// took up, or `None` if the class is never closed.
+fn class_match(pattern: &[char], c: Option<&char>) -> Option<(bool, usize)> {
+    let c = *c?;
+    let mut i = 0;
+    let negated = matches!(pattern.first(), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut found = false;
+    let mut first = true;
+    loop {
+        let lo = *pattern.get(i)?;
+        if lo == ']' && !first {
+            return Some((found != negated && c != '/', i + 1));
+        }
+        first = false;
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&hi| hi != ']') {
+            let hi = pattern[i + 2];
+            found |= lo <= c && c <= hi;
+            i += 3;
+        } else {
+            found |= lo == c;
+            i += 1;
+        }
+    }
+}
+
+// A gitignore-style pattern as a glob for paths relative to where it
+// applies: a leading `/` anchors it there, as does a `/` anywhere else, and
+// a pattern without one gets a `**/` prefix so it matches at any depth.
+pub fn anchor(pattern: &str) -> String {
+    if let Some(anchored) = pattern.strip_prefix('/') {
+        anchored.to_string()
+    } else if pattern.contains('/') {
+        pattern.to_string()
+    } else {
+        format!("**/{}", pattern)
+    }
+}
+
+// The `--glob` rules for which files under a directory get searched, as in
+// ripgrep. `*.rs` includes matching files and `!target/**` excludes them;
+// once there's any including glob, files that match none are left out.
+// Like gitignore, a glob without a `/` can match at any depth, and the last
+// glob to match a path decides.
+//
+// These globs are the last word: they override ignore files.
+#[derive(Debug, Clone, Default)]
+pub struct GlobFilter {
+    // Each glob, and whether it includes (rather than excludes).
+    globs: Vec<(Glob, bool)>,
+    has_include: bool,
+}
+
+impl GlobFilter {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> GlobFilter {
+        let globs: Vec<(Glob, bool)> = patterns
+            .iter()
+            .map(|pattern| {
+                let pattern = pattern.as_ref();
+                let (include, pattern) = match pattern.strip_prefix('!') {
+                    Some(rest) => (false, rest),
+                    None => (true, pattern),
+                };
+                (Glob::new(&anchor(pattern)), include)
+            })
+            .collect();
+        let has_include = globs.iter().any(|&(_, include)| include);
+        GlobFilter { globs, has_include }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.globs.is_empty()
+    }
+
+    // `Some(true)` if a glob includes `path` (relative to the directory
+    // being searched), `Some(false)` if one excludes it, and `None` if none
+    // of them mention it.
+    pub fn matched(&self, path: &str) -> Option<bool> {
+        let path: Vec<char> = path.chars().collect();
+        self.globs
+            .iter()
+            .rev()
+            .find(|(glob, _)| glob.is_match_chars(&path))
+            .map(|&(_, include)| include)
+    }
+
+    // Whether a file that no glob mentions is searched.
+    pub fn includes_unmatched(&self) -> bool {
+        !self.has_include
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, text: &str) -> bool {
+        Glob::new(pattern).is_match(text)
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(glob("*.rs", "lib.rs"));
+        assert!(!glob("*.rs", "src/lib.rs"));
+        assert!(glob("**/*.rs", "src/lib.rs"));
+        assert!(glob("**/*.rs", "lib.rs"));
+        assert!(glob("target/**", "target/debug/minigrep"));
+        assert!(glob("a/**/b", "a/b"));
+        assert!(glob("a/**/b", "a/x/y/b"));
+        assert!(glob("file?.[ch]", "file1.c"));
+        assert!(!glob("file?.[!ch]", "file1.c"));
+        assert!(glob("[a-c]x", "bx"));
+        assert!(glob("[^a-c]x", "dx"));
+        assert!(glob("a\\*", "a*"));
+        assert!(!glob("a\\*", "ab"));
+        assert!(glob("[x", "[x"));
+        assert!(glob("a**", "abc"));
+        assert!(!glob("a**", "a/b"));
+        assert!(glob("src/***/x", "src/a/b/x"));
+    }
+
+    #[test]
+    fn no_exponential_backtracking() {
+        let text = "a".repeat(100);
+        assert!(!glob(&"*a".repeat(50).replace("*a*a", "*a*b"), &text));
+        let path = ["a"; 40].join("/");
+        assert!(!glob(&"**/a/".repeat(20), &path));
+    }
+
+    #[test]
+    fn filter() {
+        let filter = GlobFilter::new(&["*.rs", "!target/**", "!/build.rs"]);
+        assert_eq!(Some(true), filter.matched("src/lib.rs"));
+        assert_eq!(Some(false), filter.matched("target/debug/build/x.rs"));
+        assert_eq!(Some(false), filter.matched("build.rs"));
+        assert_eq!(Some(true), filter.matched("src/build.rs"));
+        assert_eq!(None, filter.matched("README.md"));
+        assert!(!filter.includes_unmatched());
+
+        // Only excluding globs: everything else is still searched.
+        let filter = GlobFilter::new(&["!*.min.js"]);
+        assert_eq!(Some(false), filter.matched("static/app.min.js"));
+        assert!(filter.includes_unmatched());
+        // The last glob to match wins.
+        let filter = GlobFilter::new(&["!*.log", "keep.log"]);
+        assert_eq!(Some(true), filter.matched("logs/keep.log"));
+    }
+}
@@ -0,0 +1,555 @@
+use std::io::{self, BufRead, Read};
+
+// Decompresses gzip data (RFC 1952) as it's read, so a compressed log can be
+// searched without unpacking it first. A gzip file is one or more members,
+// each a small header, a DEFLATE stream (RFC 1951) and a trailer with the
+// CRC-32 and length of the uncompressed data, which are checked.
+pub struct GzDecoder<R> {
+    inflate: Inflate<R>,
+    state: State,
+    crc: u32,
+    size: u32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Header,
+    Body,
+    Done,
+}
+
+impl<R: BufRead> GzDecoder<R> {
+    pub fn new(reader: R) -> GzDecoder<R> {
+        GzDecoder {
+            inflate: Inflate::new(reader),
+            state: State::Header,
+            crc: !0,
+            size: 0,
+        }
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        const FHCRC: u8 = 0x02;
+        const FEXTRA: u8 = 0x04;
+        const FNAME: u8 = 0x08;
+        const FCOMMENT: u8 = 0x10;
+
+        let bits = &mut self.inflate.bits;
+        let mut header = [0; 10];
+        bits.read_bytes(&mut header)?;
+        if header[..2] != MAGIC {
+            return Err(invalid("not in gzip format"));
+        }
+        if header[2] != 8 {
+            return Err(invalid("unknown gzip compression method"));
+        }
+        let flags = header[3];
+        if flags & FEXTRA != 0 {
+            let mut len = [0; 2];
+            bits.read_bytes(&mut len)?;
+            for _ in 0..u16::from_le_bytes(len) {
+                bits.byte()?;
+            }
+        }
+        // The original file name and a comment, both NUL-terminated.
+        for flag in [FNAME, FCOMMENT] {
+            if flags & flag != 0 {
+                while bits.byte()? != 0 {}
+            }
+        }
+        if flags & FHCRC != 0 {
+            bits.read_bytes(&mut [0; 2])?;
+        }
+        Ok(())
+    }
+
+    fn read_trailer(&mut self) -> io::Result<()> {
+        let mut trailer = [0; 8];
+        self.inflate.bits.read_bytes(&mut trailer)?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != !self.crc {
+            return Err(invalid("gzip data fails its CRC check"));
+        }
+        // The length is only kept modulo 2^32.
+        if size != self.size {
+            return Err(invalid("gzip data has the wrong length"));
+        }
+        Ok(())
+    }
+}
+
+// The first two bytes of every gzip member.
+pub const MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+impl<R: BufRead> Read for GzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.state {
+                State::Header => {
+                    self.read_header()?;
+                    self.inflate.reset();
+                    self.crc = !0;
+                    self.size = 0;
+                    self.state = State::Body;
+                }
+                State::Body => {
+                    let n = self.inflate.read(buf)?;
+                    if n > 0 || buf.is_empty() {
+                        self.crc = crc32(self.crc, &buf[..n]);
+                        self.size = self.size.wrapping_add(n as u32);
+                        return Ok(n);
+                    }
+                    self.read_trailer()?;
+                    // `cat a.gz b.gz` is a valid gzip file too, so carry on
+                    // if another member follows.
+                    self.state = if self.inflate.bits.at_eof()? {
+                        State::Done
+                    } else {
+                        State::Header
+                    };
+                }
+                State::Done => return Ok(0),
+            }
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Reads a stream a few bits at a time, least significant bit first. Bytes
+// are only taken from the underlying reader when they're needed, so once a
+// DEFLATE stream ends, whatever follows it (the gzip trailer) hasn't been
+// swallowed.
+struct BitReader<R> {
+    inner: R,
+    bits: u32,
+    count: u32,
+}
+
+impl<R: BufRead> BitReader<R> {
+    fn next_byte(&mut self) -> io::Result<u8> {
+        let byte = match self.inner.fill_buf()?.first() {
+            Some(&byte) => byte,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "gzip data ends unexpectedly",
+                ))
+            }
+        };
+        self.inner.consume(1);
+        Ok(byte)
+    }
+
+    // The next `n` bits, for `n` up to 16.
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.count < n {
+            self.bits |= (self.next_byte()? as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.bits & ((1 << n) - 1);
+        self.bits >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    // Skips to the next byte boundary. Fewer than 8 bits are ever buffered,
+    // so this leaves none.
+    fn align(&mut self) {
+        self.bits = 0;
+        self.count = 0;
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        self.bits(8).map(|b| b as u8)
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        for b in buf {
+            *b = self.byte()?;
+        }
+        Ok(())
+    }
+
+    fn at_eof(&mut self) -> io::Result<bool> {
+        Ok(self.count == 0 && self.inner.fill_buf()?.is_empty())
+    }
+}
+
+// The sliding window: a DEFLATE back-reference reaches at most this far.
+const WINDOW: usize = 1 << 15;
+
+// Lengths and distances are coded as a symbol plus some extra bits; these
+// are the smallest value for each symbol and how many extra bits follow.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// The order in which a dynamic block lists the code lengths of its code
+// length alphabet, most common first.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+const END_OF_BLOCK: u16 = 256;
+
+// A canonical Huffman code, stored the way zlib's `puff.c` does: how many
+// codes there are of each length, and the symbols sorted by code. Since
+// codes of the same length are consecutive, that's enough to decode.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    // The code with these lengths for symbols 0, 1, 2, ..., where 0 means
+    // the symbol isn't used.
+    fn new(lengths: &[u8]) -> io::Result<Huffman> {
+        let mut counts = [0; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        // Each length has twice as many codes as the one before, less the
+        // ones used as prefixes of longer codes. Running out means the
+        // lengths are impossible.
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = left * 2 - count as i32;
+            if left < 0 {
+                return Err(invalid("invalid Huffman code in gzip data"));
+            }
+        }
+        let mut offsets = [0; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    // Reads one code a bit at a time. `code` is what's been read so far,
+    // `first` the first code of the current length and `index` where that
+    // length's symbols start.
+    fn decode<R: BufRead>(&self, bits: &mut BitReader<R>) -> io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid Huffman code in gzip data"))
+    }
+}
+
+enum Block {
+    // Between blocks.
+    Start,
+    // An uncompressed block, with this many bytes left.
+    Stored(usize),
+    Compressed {
+        literals: Huffman,
+        distances: Huffman,
+    },
+    // The block marked as the last one has ended.
+    Done,
+}
+
+// A streaming DEFLATE decoder. Every byte it produces also goes into
+// `window`, where later back-references copy from.
+struct Inflate<R> {
+    bits: BitReader<R>,
+    block: Block,
+    last_block: bool,
+    window: Window,
+    // A back-reference not yet finished: bytes left to copy, and how far back.
+    copy: (usize, usize),
+}
+
+impl<R: BufRead> Inflate<R> {
+    fn new(inner: R) -> Inflate<R> {
+        Inflate {
+            bits: BitReader {
+                inner,
+                bits: 0,
+                count: 0,
+            },
+            block: Block::Start,
+            last_block: false,
+            window: Window {
+                bytes: vec![0; WINDOW],
+                written: 0,
+            },
+            copy: (0, 0),
+        }
+    }
+
+    // Ready for another stream, as in the next gzip member.
+    fn reset(&mut self) {
+        self.block = Block::Start;
+        self.last_block = false;
+        self.window.written = 0;
+        self.copy = (0, 0);
+    }
+
+    // Fills `buf` as far as it can, returning 0 at the end of the stream.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < buf.len() {
+            if self.copy.0 > 0 {
+                let (len, distance) = self.copy;
+                let b = self.window.back(distance);
+                self.window.push(buf, &mut n, b);
+                self.copy = (len - 1, distance);
+                continue;
+            }
+            match &mut self.block {
+                Block::Start if self.last_block => {
+                    self.bits.align();
+                    self.block = Block::Done;
+                }
+                Block::Start => self.start_block()?,
+                Block::Stored(0) => self.block = Block::Start,
+                Block::Stored(left) => {
+                    *left -= 1;
+                    let b = self.bits.byte()?;
+                    self.window.push(buf, &mut n, b);
+                }
+                Block::Compressed {
+                    literals,
+                    distances,
+                } => {
+                    let symbol = literals.decode(&mut self.bits)?;
+                    if symbol < END_OF_BLOCK {
+                        self.window.push(buf, &mut n, symbol as u8);
+                    } else if symbol == END_OF_BLOCK {
+                        self.block = Block::Start;
+                    } else {
+                        let i = (symbol - 257) as usize;
+                        if i >= LENGTH_BASE.len() {
+                            return Err(invalid("invalid length code in gzip data"));
+                        }
+                        let len = LENGTH_BASE[i] as usize
+                            + self.bits.bits(LENGTH_EXTRA[i] as u32)? as usize;
+                        let i = distances.decode(&mut self.bits)? as usize;
+                        if i >= DISTANCE_BASE.len() {
+                            return Err(invalid("invalid distance code in gzip data"));
+                        }
+                        let distance = DISTANCE_BASE[i] as usize
+                            + self.bits.bits(DISTANCE_EXTRA[i] as u32)? as usize;
+                        if distance > self.window.written {
+                            return Err(invalid("gzip data refers back before its start"));
+                        }
+                        self.copy = (len, distance);
+                    }
+                }
+                Block::Done => break,
+            }
+        }
+        Ok(n)
+    }
+
+    fn start_block(&mut self) -> io::Result<()> {
+        self.last_block = self.bits.bits(1)? == 1;
+        self.block = match self.bits.bits(2)? {
+            0 => {
+                self.bits.align();
+                let len = self.bits.bits(16)?;
+                let complement = self.bits.bits(16)?;
+                if len != !complement & 0xffff {
+                    return Err(invalid("corrupt stored block in gzip data"));
+                }
+                Block::Stored(len as usize)
+            }
+            1 => {
+                // The fixed code that RFC 1951 spells out.
+                let mut lengths = [0; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                Block::Compressed {
+                    literals: Huffman::new(&lengths)?,
+                    distances: Huffman::new(&[5; 30])?,
+                }
+            }
+            2 => self.dynamic_block()?,
+            _ => return Err(invalid("invalid block type in gzip data")),
+        };
+        Ok(())
+    }
+
+    // A block with its own codes, which are themselves sent Huffman coded.
+    fn dynamic_block(&mut self) -> io::Result<Block> {
+        let literal_count = self.bits.bits(5)? as usize + 257;
+        let distance_count = self.bits.bits(5)? as usize + 1;
+        let code_length_count = self.bits.bits(4)? as usize + 4;
+        if literal_count > 286 || distance_count > 30 {
+            return Err(invalid("too many codes in gzip data"));
+        }
+
+        let mut code_lengths = [0; 19];
+        for &i in &CODE_LENGTH_ORDER[..code_length_count] {
+            code_lengths[i] = self.bits.bits(3)? as u8;
+        }
+        let code_lengths = Huffman::new(&code_lengths)?;
+
+        let mut lengths = vec![0; literal_count + distance_count];
+        let mut i = 0;
+        while i < lengths.len() {
+            let symbol = code_lengths.decode(&mut self.bits)?;
+            // 0-15 are lengths; the rest repeat a length several times.
+            let (len, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 if i > 0 => (lengths[i - 1], 3 + self.bits.bits(2)?),
+                16 => return Err(invalid("invalid code lengths in gzip data")),
+                17 => (0, 3 + self.bits.bits(3)?),
+                _ => (0, 11 + self.bits.bits(7)?),
+            };
+            let end = i + repeat as usize;
+            if end > lengths.len() {
+                return Err(invalid("invalid code lengths in gzip data"));
+            }
+            lengths[i..end].fill(len);
+            i = end;
+        }
+        if lengths[END_OF_BLOCK as usize] == 0 {
+            return Err(invalid("gzip block has no end code"));
+        }
+        let (literals, distances) = lengths.split_at(literal_count);
+        Ok(Block::Compressed {
+            literals: Huffman::new(literals)?,
+            distances: Huffman::new(distances)?,
+        })
+    }
+}
+
+struct Window {
+    bytes: Vec<u8>,
+    // Bytes produced so far; the next one goes in `bytes[written % WINDOW]`.
+    written: usize,
+}
+
+impl Window {
+    // Appends `b` to the output, `buf[*n]`, as well as the window.
+    fn push(&mut self, buf: &mut [u8], n: &mut usize, b: u8) {
+        buf[*n] = b;
+        *n += 1;
+        self.bytes[self.written % WINDOW] = b;
+        self.written += 1;
+    }
+
+    // The byte `distance` bytes back.
+    fn back(&self, distance: usize) -> u8 {
+        self.bytes[(self.written - distance) % WINDOW]
+    }
+}
+
+// CRC-32 as gzip uses it (the reflected IEEE polynomial), one table lookup
+// per byte. Start from `!0` and invert the result.
+fn crc32(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &b in bytes {
+        crc = CRC_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decompress(data: &[u8]) -> io::Result<String> {
+        let mut text = String::new();
+        GzDecoder::new(data).read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(0xcbf4_3926, !crc32(!0, b"123456789"));
+    }
+
+    #[test]
+    fn stored_and_fixed_blocks() {
+        // `printf 'hello\n' | gzip -n`, with a fixed Huffman block.
+        let fixed = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xcb, 0x48, 0xcd, 0xc9,
+            0xc9, 0xe7, 0x02, 0x00, 0x20, 0x30, 0x3a, 0x36, 0x06, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!("hello\n", decompress(&fixed).unwrap());
+
+        // The same text in a stored block, with a file name in the header,
+        // then the first member again.
+        let mut stored = vec![0x1f, 0x8b, 0x08, 0x08, 0, 0, 0, 0, 0, 0x03];
+        stored.extend(b"a.txt\0");
+        stored.extend([0x01, 0x06, 0x00, 0xf9, 0xff]);
+        stored.extend(b"hello\n");
+        stored.extend([0x20, 0x30, 0x3a, 0x36, 0x06, 0x00, 0x00, 0x00]);
+        stored.extend(fixed);
+        assert_eq!("hello\nhello\n", decompress(&stored).unwrap());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(decompress(b"not gzip").is_err());
+        // A bad CRC.
+        let bad = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xcb, 0x48, 0xcd, 0xc9,
+            0xc9, 0xe7, 0x02, 0x00, 0x21, 0x30, 0x3a, 0x36, 0x06, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            decompress(&bad).unwrap_err().kind()
+        );
+        // Cut off part way through.
+        assert_eq!(
+            io::ErrorKind::UnexpectedEof,
+            decompress(&bad[..15]).unwrap_err().kind()
+        );
+    }
+}
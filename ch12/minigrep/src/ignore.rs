@@ -0,0 +1,161 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::glob::{self, Glob};
+use crate::Warning;
+
+// The ignore files we honour in every directory, in the order they are
+// read. Rules from `.ignore` come later, so they win over `.gitignore`.
+pub const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+// One line of a `.gitignore` style file.
+#[derive(Debug, PartialEq)]
+struct Rule {
+    // The pattern, already anchored: a pattern without an inner `/` gets a
+    // `**/` prefix so it matches at any depth below the ignore file.
+    glob: Glob,
+    // `!pattern` re-includes a path an earlier rule excluded.
+    negated: bool,
+    // `pattern/` only matches directories.
+    dir_only: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        if line.is_empty() {
+            return None;
+        }
+
+        Some(Rule {
+            glob: Glob::new(&glob::anchor(line)),
+            negated,
+            dir_only,
+        })
+    }
+}
+
+// The rules from the ignore files of a single directory.
+#[derive(Debug)]
+pub struct Ignore {
+    dir: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl Ignore {
+    // Reads the ignore files in `dir`, if there are any. One that can't be
+    // read is passed to `warn`, and ignores nothing.
+    pub fn load(dir: &Path, warn: &mut dyn FnMut(Warning)) -> Option<Ignore> {
+        let mut contents = String::new();
+        for name in IGNORE_FILES.iter() {
+            let path = dir.join(name);
+            match fs::read_to_string(&path) {
+                Ok(text) => {
+                    contents.push_str(&text);
+                    contents.push('\n');
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => warn(Warning { path, error }),
+            }
+        }
+        let ignore = Ignore::parse(dir, &contents);
+        if ignore.rules.is_empty() {
+            None
+        } else {
+            Some(ignore)
+        }
+    }
+
+    pub fn parse(dir: &Path, contents: &str) -> Ignore {
+        Ignore {
+            dir: dir.to_path_buf(),
+            rules: contents.lines().filter_map(Rule::parse).collect(),
+        }
+    }
+
+    // `Some(true)` if `path` is ignored by these rules, `Some(false)` if a
+    // negated rule whitelists it, and `None` if no rule mentions it.
+    pub fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.dir).ok()?;
+        let relative: Vec<char> = relative
+            .to_string_lossy()
+            .replace(std::path::MAIN_SEPARATOR, "/")
+            .chars()
+            .collect();
+
+        // The last matching rule wins.
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.glob.is_match_chars(&relative))
+            .map(|rule| !rule.negated)
+    }
+}
+
+// Every `Ignore` between the search root and the current directory. Deeper
+// directories are checked first, since their rules take precedence.
+pub fn is_ignored(stack: &[Ignore], path: &Path, is_dir: bool) -> bool {
+    stack
+        .iter()
+        .rev()
+        .find_map(|ignore| ignore.matched(path, is_dir))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules() {
+        let root = Path::new("/repo");
+        let ignore = Ignore::parse(
+            root,
+            "# build output\n\
+             target/\n\
+             *.log\n\
+             !keep.log\n\
+             /TODO\n",
+        );
+        assert_eq!(Some(true), ignore.matched(Path::new("/repo/target"), true));
+        assert_eq!(None, ignore.matched(Path::new("/repo/target"), false));
+        assert_eq!(
+            Some(true),
+            ignore.matched(Path::new("/repo/a/b.log"), false)
+        );
+        assert_eq!(
+            Some(false),
+            ignore.matched(Path::new("/repo/keep.log"), false)
+        );
+        assert_eq!(Some(true), ignore.matched(Path::new("/repo/TODO"), false));
+        assert_eq!(None, ignore.matched(Path::new("/repo/src/TODO"), false));
+    }
+
+    #[test]
+    fn deeper_rules_win() {
+        let stack = vec![
+            Ignore::parse(Path::new("/repo"), "*.txt"),
+            Ignore::parse(Path::new("/repo/docs"), "!notes.txt"),
+        ];
+        assert!(is_ignored(&stack, Path::new("/repo/a.txt"), false));
+        assert!(!is_ignored(
+            &stack,
+            Path::new("/repo/docs/notes.txt"),
+            false
+        ));
+        assert!(is_ignored(&stack, Path::new("/repo/docs/other.txt"), false));
+    }
+}
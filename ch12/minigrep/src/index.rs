@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::time::UNIX_EPOCH;
+
+use crate::decompress;
+use crate::encoding;
+use crate::replace;
+
+// Where in an indexed directory its index is kept.
+pub const FILE_NAME: &str = ".minigrep-index";
+
+const MAGIC: &[u8] = b"minigrep index 1\n";
+
+type Trigram = [u8; 3];
+
+// Which files under a directory have which trigrams (runs of three bytes)
+// in them, so that a search can skip the files that can't match. Every
+// match of `timeout` has `tim`, `ime`, `meo`, `eou` and `out` in it, so
+// only the files with all five are worth searching; usually that's a small
+// fraction of a big tree, even if some of them turn out not to match.
+//
+// `minigrep index DIR` builds one, and `minigrep --index QUERY DIR` uses
+// it. Each file's size and modification time are kept alongside, and files
+// that have changed since are indexed again the next time the index is
+// used, so it's never out of date, just slower to use the more has
+// changed.
+#[derive(Debug, Default)]
+pub struct Index {
+    files: Vec<Entry>,
+    // The files each trigram is in, as sorted indexes into `files`.
+    postings: HashMap<Trigram, Vec<u32>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    // Relative to the indexed directory, with `/` between components.
+    path: String,
+    // Nanoseconds since the Unix epoch.
+    modified: u64,
+    len: u64,
+    // Whether the file's trigrams are in the index. Text that isn't UTF-8
+    // is decoded as Latin-1 when it's searched, so a query could match
+    // bytes that aren't its own; such a file, or one that couldn't be read,
+    // is always searched.
+    indexed: bool,
+}
+
+impl Index {
+    // The index kept in `root`, or `None` if there isn't one.
+    pub fn open(root: &Path) -> io::Result<Option<Index>> {
+        let bytes = match fs::read(root.join(FILE_NAME)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Decoder { bytes: &bytes }.index().map(Some).ok_or_else(|| {
+            let message = format!(
+                "the index in {} is damaged or from another version; \
+                 rebuild it with `minigrep index`",
+                root.display()
+            );
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        })
+    }
+
+    pub fn save(&self, root: &Path) -> io::Result<()> {
+        let mut out = MAGIC.to_vec();
+        put_varint(&mut out, self.files.len() as u64);
+        for entry in &self.files {
+            put_varint(&mut out, entry.path.len() as u64);
+            out.extend_from_slice(entry.path.as_bytes());
+            put_varint(&mut out, entry.modified);
+            put_varint(&mut out, entry.len);
+            out.push(entry.indexed as u8);
+        }
+        // Sorted, so the same index is always written the same way.
+        let mut trigrams: Vec<_> = self.postings.iter().collect();
+        trigrams.sort_unstable_by_key(|&(trigram, _)| trigram);
+        put_varint(&mut out, trigrams.len() as u64);
+        for (trigram, ids) in trigrams {
+            out.extend_from_slice(trigram);
+            put_varint(&mut out, ids.len() as u64);
+            // Each id as the gap from the one before, which is small.
+            let mut last = 0;
+            for &id in ids {
+                put_varint(&mut out, (id - last) as u64);
+                last = id;
+            }
+        }
+        replace::write_atomically(&root.join(FILE_NAME), &out)
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    // Brings the index of `root` up to date with `files`, found by walking
+    // it: new files and ones that have changed are indexed, and ones that
+    // no longer exist are dropped. Returns how many files that was.
+    //
+    // Files that are indexed but aren't in `files` are left alone if they
+    // still exist, since they may just have been filtered out of this walk.
+    pub fn update(&mut self, root: &Path, files: &[PathBuf]) -> io::Result<usize> {
+        let ids: HashMap<&str, usize> = self
+            .files
+            .iter()
+            .enumerate()
+            .map(|(id, entry)| (entry.path.as_str(), id))
+            .collect();
+        let mut keep = vec![true; self.files.len()];
+        let mut seen = vec![false; self.files.len()];
+        let mut changed = Vec::new();
+        for file in files {
+            let path = match relative(root, file) {
+                Some(path) => path,
+                None => continue,
+            };
+            let metadata = match fs::metadata(file) {
+                Ok(metadata) => metadata,
+                // Gone since the walk found it.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let modified = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64);
+            if let Some(&id) = ids.get(path.as_str()) {
+                seen[id] = true;
+                let entry = &self.files[id];
+                if entry.modified == modified && entry.len == metadata.len() {
+                    continue;
+                }
+                keep[id] = false;
+            }
+            changed.push((file, path, modified, metadata.len()));
+        }
+        for (id, entry) in self.files.iter().enumerate() {
+            if !seen[id] && !root.join(&entry.path).is_file() {
+                keep[id] = false;
+            }
+        }
+        let gone = (0..keep.len()).filter(|&id| !keep[id] && !seen[id]).count();
+        let updated = changed.len() + gone;
+        if keep.contains(&false) {
+            self.drop_files(&keep);
+        }
+
+        for (file, path, modified, len) in changed {
+            let id = self.files.len() as u32;
+            let trigrams = trigrams(file).ok().flatten();
+            let indexed = trigrams.is_some();
+            for trigram in trigrams.unwrap_or_default() {
+                // Files are only ever added at the end, so the lists stay
+                // sorted.
+                self.postings.entry(trigram).or_default().push(id);
+            }
+            self.files.push(Entry {
+                path,
+                modified,
+                len,
+                indexed,
+            });
+        }
+        Ok(updated)
+    }
+
+    // Removes the files that aren't to be kept, renumbering the rest.
+    fn drop_files(&mut self, keep: &[bool]) {
+        let mut ids = Vec::with_capacity(keep.len());
+        let mut next = 0;
+        for &keep in keep {
+            ids.push(if keep { Some(next) } else { None });
+            next += keep as u32;
+        }
+        let mut keep = keep.iter();
+        self.files.retain(|_| *keep.next().unwrap());
+        self.postings.retain(|_, list| {
+            *list = list.iter().filter_map(|&id| ids[id as usize]).collect();
+            !list.is_empty()
+        });
+    }
+
+    // The `files` in `root` that might have a match for one of `queries`,
+    // which are plain text. Files that aren't in the index are kept, so
+    // they're searched to find out.
+    pub fn candidates(
+        &self,
+        root: &Path,
+        files: Vec<PathBuf>,
+        queries: &[String],
+        case_sensitive: bool,
+    ) -> Vec<PathBuf> {
+        let mut matched = vec![false; self.files.len()];
+        for query in queries {
+            let required = required(query, case_sensitive);
+            // Too short, or nothing in it that can be looked up.
+            if required.is_empty() {
+                return files;
+            }
+            let mut ids: Option<Vec<u32>> = None;
+            for spellings in required {
+                let mut with: Vec<u32> = spellings
+                    .iter()
+                    .filter_map(|trigram| self.postings.get(trigram))
+                    .flatten()
+                    .copied()
+                    .collect();
+                with.sort_unstable();
+                with.dedup();
+                ids = Some(match ids {
+                    Some(ids) => ids
+                        .into_iter()
+                        .filter(|id| with.binary_search(id).is_ok())
+                        .collect(),
+                    None => with,
+                });
+            }
+            for id in ids.unwrap_or_default() {
+                matched[id as usize] = true;
+            }
+        }
+        let ids: HashMap<&str, usize> = self
+            .files
+            .iter()
+            .enumerate()
+            .map(|(id, entry)| (entry.path.as_str(), id))
+            .collect();
+        files
+            .into_iter()
+            .filter(|file| {
+                let id = relative(root, file).and_then(|path| ids.get(path.as_str()).copied());
+                id.is_none_or(|id| matched[id] || !self.files[id].indexed)
+            })
+            .collect()
+    }
+}
+
+// `file`'s path within `root`, as it's kept in the index. A path that isn't
+// UTF-8 can't be, and the file is never skipped.
+fn relative(root: &Path, file: &Path) -> Option<String> {
+    let path = file.strip_prefix(root).ok()?.to_str()?;
+    Some(path.replace(std::path::MAIN_SEPARATOR, "/"))
+}
+
+// Every trigram in the file at `path`, as a search would see its text:
+// decompressed, and decoded if it's UTF-16. `None` if it's not UTF-8.
+fn trigrams(path: &Path) -> io::Result<Option<Vec<Trigram>>> {
+    let mut bytes = Vec::new();
+    encoding::decode(decompress::open(path)?)?.read_to_end(&mut bytes)?;
+    if str::from_utf8(&bytes).is_err() {
+        return Ok(None);
+    }
+    let mut trigrams: Vec<Trigram> = bytes.windows(3).map(|w| [w[0], w[1], w[2]]).collect();
+    trigrams.sort_unstable();
+    trigrams.dedup();
+    Ok(Some(trigrams))
+}
+
+// The trigrams any match of `query` has in it, each with every way it can
+// be spelled: just the one, unless case is ignored.
+//
+// Ignoring case, only ASCII letters have a known set of spellings, and even
+// then 's' and 'k' also match 'ſ' (long s) and 'K' (kelvin sign), so
+// trigrams with those or anything that isn't ASCII are left out.
+fn required(query: &str, case_sensitive: bool) -> Vec<Vec<Trigram>> {
+    query
+        .as_bytes()
+        .windows(3)
+        .filter_map(|w| {
+            let trigram = [w[0], w[1], w[2]];
+            if case_sensitive {
+                return Some(vec![trigram]);
+            }
+            let ambiguous = |&b: &u8| !b.is_ascii() || matches!(b, b's' | b'S' | b'k' | b'K');
+            if trigram.iter().any(ambiguous) {
+                return None;
+            }
+            let mut spellings = vec![trigram];
+            for i in 0..3 {
+                if trigram[i].is_ascii_alphabetic() {
+                    let flipped: Vec<Trigram> = spellings
+                        .iter()
+                        .map(|&s| {
+                            let mut s = s;
+                            s[i] ^= 0x20;
+                            s
+                        })
+                        .collect();
+                    spellings.extend(flipped);
+                }
+            }
+            Some(spellings)
+        })
+        .collect()
+}
+
+// LEB128, as in protobuf and WebAssembly: seven bits at a time, low bits
+// first, with the top bit set on all but the last byte.
+fn put_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+// Reads an index back from what `save` wrote. Anything unexpected is `None`.
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn index(&mut self) -> Option<Index> {
+        if self.take(MAGIC.len())? != MAGIC {
+            return None;
+        }
+        let mut index = Index::default();
+        for _ in 0..self.varint()? {
+            let len = self.varint()? as usize;
+            let path = str::from_utf8(self.take(len)?).ok()?.to_string();
+            index.files.push(Entry {
+                path,
+                modified: self.varint()?,
+                len: self.varint()?,
+                indexed: self.take(1)?[0] != 0,
+            });
+        }
+        for _ in 0..self.varint()? {
+            let trigram = self.take(3)?;
+            let trigram = [trigram[0], trigram[1], trigram[2]];
+            let mut ids = Vec::new();
+            let mut id = 0u32;
+            for _ in 0..self.varint()? {
+                id = id.checked_add(u32::try_from(self.varint()?).ok()?)?;
+                if id as usize >= index.files.len() {
+                    return None;
+                }
+                ids.push(id);
+            }
+            index.postings.insert(trigram, ids);
+        }
+        match self.bytes.is_empty() {
+            true => Some(index),
+            false => None,
+        }
+    }
+
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < n {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn varint(&mut self) -> Option<u64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            n |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return Some(n);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::walk;
+    use crate::GlobFilter;
+    use std::env;
+
+    fn names(root: &Path, files: Vec<PathBuf>) -> Vec<String> {
+        files
+            .iter()
+            .map(|file| relative(root, file).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn narrows_and_updates() {
+        let root = env::temp_dir().join(format!("minigrep-index-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), "connection timeout\n").unwrap();
+        fs::write(root.join("sub/b.txt"), "time out\n").unwrap();
+        fs::write(root.join("latin1.txt"), b"caf\xe9\n").unwrap();
+        let walk = || {
+            walk::walk(&root, &[], &GlobFilter::new::<&str>(&[]), &mut |w| {
+                panic!("{}", w)
+            })
+        };
+        let candidates = |index: &Index, query: &str, case_sensitive: bool| {
+            let files = index.candidates(&root, walk(), &[query.to_string()], case_sensitive);
+            names(&root, files)
+        };
+
+        let mut index = Index::default();
+        assert_eq!(3, index.update(&root, &walk()).unwrap());
+        index.save(&root).unwrap();
+        // The index itself is never searched.
+        assert_eq!(3, walk().len());
+        let mut index = Index::open(&root).unwrap().unwrap();
+        assert_eq!(0, index.update(&root, &walk()).unwrap());
+        // A file that isn't UTF-8 is always a candidate.
+        assert_eq!(
+            vec!["a.txt", "latin1.txt"],
+            candidates(&index, "timeout", true)
+        );
+        assert_eq!(
+            vec!["a.txt", "latin1.txt"],
+            candidates(&index, "TIMEout", false)
+        );
+        assert_eq!(3, candidates(&index, "ti", true).len());
+        // Nothing can be looked up for a 'k' when case is ignored.
+        assert_eq!(3, candidates(&index, "kkk", false).len());
+
+        // Only the file that changed is indexed again, and one that's gone
+        // is dropped.
+        fs::write(root.join("sub/b.txt"), "a timeout\n").unwrap();
+        fs::remove_file(root.join("latin1.txt")).unwrap();
+        assert_eq!(2, index.update(&root, &walk()).unwrap());
+        assert_eq!(2, index.len());
+        assert_eq!(
+            vec!["a.txt", "sub/b.txt"],
+            candidates(&index, "timeout", true)
+        );
+        assert_eq!(vec!["a.txt"], candidates(&index, "connection", true));
+
+        fs::write(root.join(FILE_NAME), "not an index").unwrap();
+        assert!(Index::open(&root).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,388 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::encoding::raw_offset;
+use crate::searcher::Stats;
+use crate::{Match, Sink};
+
+// Just enough JSON to describe search results. Objects keep their keys in
+// insertion order so the output is stable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(u64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn object(fields: Vec<(&str, Value)>) -> Value {
+        Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    fn text(text: &str) -> Value {
+        Value::object(vec![("text", Value::String(text.to_string()))])
+    }
+
+    // Bytes as ripgrep puts them: as text if they're UTF-8, and otherwise
+    // base64-encoded, so they come out exactly as they were.
+    fn bytes(bytes: &[u8]) -> Value {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Value::text(text),
+            Err(_) => Value::object(vec![("bytes", Value::String(base64(bytes)))]),
+        }
+    }
+}
+
+// Standard base64, with padding.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// `Display` writes compact JSON on a single line.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write_string(f, s),
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+// The events below follow ripgrep's `--json` format, one object per line:
+//
+//   {"type":"begin","data":{"path":{"text":"poem.txt"}}}
+//   {"type":"match","data":{"path":...,"lines":...,"line_number":1,...}}
+//   {"type":"end","data":{"path":...,"stats":{...}}}
+//   {"type":"summary","data":{"stats":{...}}}
+
+fn event(kind: &str, data: Value) -> Value {
+    Value::object(vec![
+        ("type", Value::String(kind.to_string())),
+        ("data", data),
+    ])
+}
+
+pub fn begin(path: &Path) -> Value {
+    event(
+        "begin",
+        Value::object(vec![("path", Value::text(&path.to_string_lossy()))]),
+    )
+}
+
+// A matching line. Byte offsets in `submatches` are relative to the line.
+pub fn matched(path: &Path, m: &Match) -> Value {
+    line_event("match", path, m)
+}
+
+pub fn context(path: &Path, m: &Match) -> Value {
+    line_event("context", path, m)
+}
+
+fn line_event(kind: &str, path: &Path, m: &Match) -> Value {
+    // `lines` is the line as it was read, so the spans have to be offsets
+    // into that, rather than into the text it was decoded to.
+    let submatches = m
+        .spans
+        .iter()
+        .map(|&(start, end)| (raw_offset(m.raw, start), raw_offset(m.raw, end)))
+        .map(|(start, end)| {
+            Value::object(vec![
+                ("match", Value::bytes(&m.raw[start..end])),
+                ("start", Value::Number(start as u64)),
+                ("end", Value::Number(end as u64)),
+            ])
+        })
+        .collect();
+    let mut fields = vec![
+        ("path", Value::text(&path.to_string_lossy())),
+        ("lines", Value::bytes(m.raw)),
+        ("line_number", Value::Number(m.line_number as u64)),
+        ("absolute_offset", Value::Number(m.byte_offset as u64)),
+        ("submatches", Value::Array(submatches)),
+    ];
+    // Not part of ripgrep's format; only `--fuzzy` matches have one.
+    if let Some(distance) = m.edit_distance {
+        fields.push(("edit_distance", Value::Number(distance as u64)));
+    }
+    event(kind, Value::object(fields))
+}
+
+pub fn end(path: &Path, stats: &Stats) -> Value {
+    event(
+        "end",
+        Value::object(vec![
+            ("path", Value::text(&path.to_string_lossy())),
+            ("stats", stats_value(stats)),
+        ]),
+    )
+}
+
+pub fn summary(stats: &Stats) -> Value {
+    event(
+        "summary",
+        Value::object(vec![("stats", stats_value(stats))]),
+    )
+}
+
+fn stats_value(stats: &Stats) -> Value {
+    Value::object(vec![
+        ("searches", Value::Number(stats.searches as u64)),
+        (
+            "searches_with_match",
+            Value::Number(stats.searches_with_match as u64),
+        ),
+        ("bytes_searched", Value::Number(stats.bytes_searched as u64)),
+        ("matched_lines", Value::Number(stats.matched_lines as u64)),
+        ("matches", Value::Number(stats.matches as u64)),
+    ])
+}
+
+// `--json`: a `Sink` that writes the events above, one per line. Groups,
+// counts and file names are left out; the `end` and `summary` events carry
+// the same information.
+pub struct JsonPrinter<W: Write> {
+    out: W,
+    // Whether a `begin` event has been written for the current file. Like
+    // ripgrep, files without any lines to report get no begin/end events.
+    in_file: bool,
+    // There's no event for a binary file matching, so once a file turns out
+    // to be binary, it's left out.
+    binary: bool,
+}
+
+impl<W: Write> JsonPrinter<W> {
+    pub fn new(out: W) -> JsonPrinter<W> {
+        JsonPrinter {
+            out,
+            in_file: false,
+            binary: false,
+        }
+    }
+
+    // Called once everything has been searched, with the totals.
+    pub fn print_summary(&mut self, stats: &Stats) -> io::Result<()> {
+        writeln!(self.out, "{}", summary(stats))
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn begin_file(&mut self, path: &Path) -> io::Result<()> {
+        if !self.in_file {
+            self.in_file = true;
+            writeln!(self.out, "{}", begin(path))?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Sink for JsonPrinter<W> {
+    fn begin(&mut self, _path: &Path) -> io::Result<()> {
+        self.binary = false;
+        Ok(())
+    }
+
+    fn matched(&mut self, path: &Path, m: &Match) -> io::Result<bool> {
+        if self.binary {
+            return Ok(false);
+        }
+        self.begin_file(path)?;
+        writeln!(self.out, "{}", matched(path, m))?;
+        Ok(true)
+    }
+
+    fn context(&mut self, path: &Path, m: &Match) -> io::Result<bool> {
+        self.begin_file(path)?;
+        writeln!(self.out, "{}", context(path, m))?;
+        Ok(true)
+    }
+
+    fn binary_data(&mut self, _path: &Path, _byte_offset: usize) -> io::Result<bool> {
+        self.binary = true;
+        Ok(true)
+    }
+
+    fn end(&mut self, path: &Path, stats: &Stats) -> io::Result<()> {
+        if self.in_file {
+            self.in_file = false;
+            writeln!(self.out, "{}", end(path, stats))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes() {
+        let value = Value::object(vec![
+            (
+                "a",
+                Value::Array(vec![Value::Null, Value::Bool(true), Value::Number(3)]),
+            ),
+            (
+                "b",
+                Value::String("quote \" slash \\ tab \t bell \u{7} é".to_string()),
+            ),
+        ]);
+        assert_eq!(
+            r#"{"a":[null,true,3],"b":"quote \" slash \\ tab \t bell \u0007 é"}"#,
+            value.to_string()
+        );
+    }
+
+    #[test]
+    fn match_event() {
+        let m = Match {
+            line_number: 2,
+            byte_offset: 10,
+            line: "a rust b",
+            raw: b"a rust b\r\n",
+            spans: vec![(2, 6)],
+            edit_distance: None,
+        };
+        assert_eq!(
+            concat!(
+                r#"{"type":"match","data":{"path":{"text":"poem.txt"},"#,
+                r#""lines":{"text":"a rust b\r\n"},"line_number":2,"absolute_offset":10,"#,
+                r#""submatches":[{"match":{"text":"rust"},"start":2,"end":6}]}}"#
+            ),
+            matched(Path::new("poem.txt"), &m).to_string()
+        );
+    }
+
+    #[test]
+    fn non_utf8_lines() {
+        assert_eq!(r#"{"text":"end"}"#, Value::bytes(b"end").to_string());
+        assert_eq!(
+            r#"{"bytes":"Y2Fm6Qo="}"#,
+            Value::bytes(b"caf\xe9\n").to_string()
+        );
+        assert_eq!("", base64(b""));
+        assert_eq!("/w==", base64(b"\xff"));
+        assert_eq!("YWI=", base64(b"ab"));
+        assert_eq!("YWJj", base64(b"abc"));
+    }
+
+    #[test]
+    fn latin1_match_offsets() {
+        let raw = b"caf\xe9 x\n";
+        let line = crate::encoding::decode_line(&raw[..raw.len() - 1]);
+        let m = Match {
+            line_number: 1,
+            byte_offset: 0,
+            line: &line,
+            raw,
+            spans: vec![(0, 5), (6, 7)],
+            edit_distance: None,
+        };
+        assert_eq!(
+            concat!(
+                r#"{"type":"match","data":{"path":{"text":"f"},"#,
+                r#""lines":{"bytes":"Y2Fm6SB4Cg=="},"line_number":1,"absolute_offset":0,"#,
+                r#""submatches":[{"match":{"bytes":"Y2Fm6Q=="},"start":0,"end":4},"#,
+                r#"{"match":{"text":"x"},"start":5,"end":6}]}}"#
+            ),
+            matched(Path::new("f"), &m).to_string()
+        );
+    }
+
+    #[test]
+    fn events() {
+        let mut printer = JsonPrinter::new(Vec::new());
+        let path = Path::new("f");
+        let stats = Stats::default();
+        let line = |line_number, line: &'static str, spans| Match {
+            line_number,
+            byte_offset: 0,
+            line,
+            raw: line.as_bytes(),
+            spans,
+            edit_distance: None,
+        };
+        // Nothing was printed for this file, so no begin/end events.
+        printer.begin(path).unwrap();
+        printer.end(path, &stats).unwrap();
+        printer.begin(path).unwrap();
+        printer.context(path, &line(1, "a", vec![])).unwrap();
+        printer.matched(path, &line(2, "x", vec![(0, 1)])).unwrap();
+        printer.end(path, &stats).unwrap();
+        printer.print_summary(&stats).unwrap();
+        let output = String::from_utf8(printer.into_inner()).unwrap();
+        let types: Vec<&str> = output
+            .lines()
+            .map(|l| &l[9..l.find("\",").unwrap()])
+            .collect();
+        assert_eq!(vec!["begin", "context", "match", "end", "summary"], types);
+    }
+}
@@ -1,60 +1,699 @@
-use std::env;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::fmt;
 use std::fs;
+use std::io::{self, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
 
-pub struct Config {
-    pub query: String,
-    pub filename: String,
-    pub case_sensitive: bool,
-}
-
-impl Config {
-    pub fn new(mut args: env::Args) -> Result<Config, &'static str> {
-        // Returning Result, instead of panicking, allows `main` to handle
-        // the Result, and exit the process more cleanly
-        args.next();
+pub mod aho_corasick;
+pub mod casefold;
+pub mod config;
+pub mod config_file;
+pub mod decompress;
+pub mod encoding;
+pub mod fuzzy;
+pub mod glob;
+pub mod gzip;
+pub mod ignore;
+pub mod index;
+pub mod json;
+pub mod literal;
+pub mod matcher;
+mod mmap;
+pub mod pool;
+pub mod printer;
+pub mod query;
+pub mod regex;
+pub mod replace;
+pub mod searcher;
+pub mod sink;
+pub mod toml;
+pub mod types;
+pub mod walk;
+pub mod watch;
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
-        };
+pub use aho_corasick::AhoCorasick;
+pub use config::{BinaryFiles, ColorChoice, Config, ConfigError, MmapChoice, OutputMode, Records};
+pub use config_file::ConfigFile;
+pub use decompress::Format;
+pub use fuzzy::{Fuzzy, PatternTooLong};
+pub use glob::{Glob, GlobFilter};
+pub use gzip::GzDecoder;
+pub use index::Index;
+pub use json::JsonPrinter;
+pub use literal::Literal;
+pub use matcher::{CaseInsensitive, Matcher, WholeLine, WholeWord};
+pub use pool::ThreadPool;
+pub use printer::{Colors, Printer};
+pub use query::Query;
+pub use regex::{Regex, RegexBuilder};
+pub use searcher::{Searcher, Stats, STDIN_LABEL};
+pub use sink::{FnSink, Sink};
+pub use types::TypeFilter;
+pub use watch::Watch;
 
-        let filename = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file name"),
-        };
+// A matching line, with enough position information to print it like
+// `grep -n` or highlight what matched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match<'a> {
+    // 1-based, as printed by `-n`.
+    pub line_number: usize,
+    // Byte offset of the start of the line within the searched contents.
+    pub byte_offset: usize,
+    pub line: &'a str,
+    // The line as it was read, with whatever ended it: a newline, a record
+    // separator, or nothing at the very end. Differs from `line` when the
+    // input isn't valid UTF-8.
+    pub raw: &'a [u8],
+    // Byte spans of every match within `line`.
+    pub spans: Vec<(usize, usize)>,
+    // How many edits the closest match on the line needed, for `--fuzzy`.
+    pub edit_distance: Option<usize>,
+}
 
-        let case_sensitive = env::var("CASE_INSENSITIVE").is_err();
+// A file or directory that couldn't be read, whether it was named on the
+// command line or found while walking a directory. It's passed over, and
+// the search goes on without it.
+#[derive(Debug)]
+pub struct Warning {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
 
-        Ok(Config {
-            query,
-            filename,
-            case_sensitive,
-        })
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.error)
     }
 }
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
+impl Error for Warning {}
+
+// `warn` is called with each `Warning` as it comes up.
+pub fn run(config: Config, warn: &mut dyn FnMut(Warning)) -> Result<(), Box<dyn Error>> {
     // Box<dyn Error> is a trait object, which means that the type will
     // implement the Error trait, but not specified to be any particular type.
-    let contents = fs::read_to_string(config.filename)?;
+    let matcher: Arc<dyn Matcher> = Arc::from(build_matcher(&config)?);
 
-    let results = if config.case_sensitive {
-        search(&config.query, &contents)
+    // With no files at all, read stdin like `tail -f log | minigrep`.
+    let paths = if config.paths.is_empty() {
+        vec!["-".to_string()]
     } else {
-        search_case_insensitive(&config.query, &contents)
+        config.paths.clone()
     };
+    // Like grep, prefix every line with the file it came from as soon as
+    // more than one file could be involved.
+    let with_filename = paths.len() > 1 || paths.iter().any(|p| Path::new(p).is_dir());
+    let inputs = collect_inputs(&config, &paths, warn)?;
+    let has_stdin = inputs.iter().any(|input| matches!(input, Input::Stdin));
+
+    if config.in_place || config.dry_run {
+        if config.in_place && has_stdin {
+            return Err("--in-place can't rewrite standard input".into());
+        }
+        return rewrite(&config, &*matcher, &inputs, warn);
+    }
 
-    for line in results {
-        println!("{}", line);
+    // Decided once up front: worker threads print into buffers, which are
+    // never terminals.
+    let color = config.color.enabled();
+    if config.watch {
+        if has_stdin {
+            return Err("--watch can't follow standard input".into());
+        }
+        return watch(&config, &*matcher, &paths, with_filename, color, warn);
+    }
+    let threads = match config.threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    // Stdin has to be streamed as it arrives, so it's never handed to the
+    // pool, where its output would be held back until it ends.
+    if threads > 1 && inputs.len() > 1 && !has_stdin {
+        return search_parallel(config, matcher, inputs, with_filename, color, threads, warn);
     }
 
+    let searcher = new_searcher(&config);
+    let stdout = io::stdout();
+    let mut output = Output::new(&config, stdout.lock(), with_filename, color);
+    let mut stats = Stats::default();
+    for input in &inputs {
+        stats += search_input(&searcher, &*matcher, input, output.sink(), warn)?;
+    }
+    output.finish(&stats)?;
     Ok(())
 }
 
-pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+fn new_searcher(config: &Config) -> Searcher {
+    // Context lines are only printed along with whole matching lines.
+    let (before, after) = match config.output {
+        OutputMode::Lines => (config.before_context, config.after_context),
+        _ => (0, 0),
+    };
+    Searcher::new()
+        .with_invert_match(config.invert_match)
+        .with_context(before, after)
+        .with_binary_files(config.binary_files)
+        .with_records(config.records.clone())
+        .with_multiline(config.multiline)
+        .with_mmap(config.mmap)
+}
+
+// The sink that `run` prints results with.
+enum Output<W: Write> {
+    Text(Printer<W>),
+    Json(JsonPrinter<W>),
+}
+
+impl<W: Write> Output<W> {
+    fn new(config: &Config, out: W, with_filename: bool, color: bool) -> Output<W> {
+        if config.json {
+            return Output::Json(JsonPrinter::new(out));
+        }
+        let printer = Printer::new(out, config.line_number)
+            .with_filename(with_filename)
+            .with_output(config.output)
+            .with_replace(config.replace.clone())
+            .with_terminator(config.records.terminator());
+        if color {
+            Output::Text(printer.with_colors(config.colors.clone()))
+        } else {
+            Output::Text(printer)
+        }
+    }
+
+    fn sink(&mut self) -> &mut dyn Sink {
+        match self {
+            Output::Text(printer) => printer,
+            Output::Json(printer) => printer,
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Text(printer) => printer.flush(),
+            Output::Json(printer) => printer.flush(),
+        }
+    }
+
+    fn wrote_group(&self) -> bool {
+        match self {
+            Output::Text(printer) => printer.wrote_group(),
+            Output::Json(_) => false,
+        }
+    }
+
+    // Called once everything has been searched, with the totals.
+    fn finish(&mut self, stats: &Stats) -> io::Result<()> {
+        match self {
+            Output::Text(printer) => printer.flush(),
+            Output::Json(printer) => {
+                printer.print_summary(stats)?;
+                printer.flush()
+            }
+        }
+    }
+
+    fn into_inner(self) -> W {
+        match self {
+            Output::Text(printer) => printer.into_inner(),
+            Output::Json(printer) => printer.into_inner(),
+        }
+    }
+}
+
+// Something to search: stdin, a file named on the command line, or a file
+// found by walking a directory.
+enum Input {
+    Stdin,
+    File(PathBuf),
+    Walked(PathBuf),
+}
+
+fn collect_inputs(
+    config: &Config,
+    paths: &[String],
+    warn: &mut dyn FnMut(Warning),
+) -> io::Result<Vec<Input>> {
+    let globs = GlobFilter::new(&config.globs);
+    let types = TypeFilter::new(&config.file_types, &config.types, &config.types_not);
+    let mut inputs = Vec::new();
+    for path in paths {
+        if path == "-" {
+            inputs.push(Input::Stdin);
+        } else if Path::new(path).is_dir() {
+            let files = walk::walk(Path::new(path), &config.ignore, &globs, warn);
+            let mut files: Vec<PathBuf> = files
+                .into_iter()
+                .filter(|file| types.allows(file))
+                .collect();
+            if config.index {
+                files = narrow(config, Path::new(path), files)?;
+            }
+            inputs.extend(files.into_iter().map(Input::Walked));
+        } else {
+            inputs.push(Input::File(PathBuf::from(path)));
+        }
+    }
+    Ok(inputs)
+}
+
+// `--index`: the files found in `root` that its index says could match,
+// once it's been brought up to date with them.
+fn narrow(config: &Config, root: &Path, files: Vec<PathBuf>) -> io::Result<Vec<PathBuf>> {
+    let mut index = Index::open(root)?.ok_or_else(|| {
+        let message = format!(
+            "{} has no index; build one with `minigrep index {}`",
+            root.display(),
+            root.display()
+        );
+        io::Error::new(io::ErrorKind::NotFound, message)
+    })?;
+    if index.update(root, &files)? > 0 {
+        index.save(root)?;
+    }
+    // Only a plain-text query says what text a match has in it, and a
+    // count or `-L` has to print something for the files that don't match
+    // too.
+    let plain = !config.regex && !config.query && config.fuzzy.is_none() && !config.invert_match;
+    match config.output {
+        OutputMode::Count | OutputMode::FilesWithoutMatch => Ok(files),
+        _ if plain => Ok(index.candidates(root, files, &config.queries, config.case_sensitive)),
+        _ => Ok(files),
+    }
+}
+
+// What `build_indexes` did to one directory's index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Indexed {
+    pub path: PathBuf,
+    // How many files it has in it now, and how many of those had to be
+    // indexed (again).
+    pub files: usize,
+    pub updated: usize,
+}
+
+impl fmt::Display for Indexed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} files, {} updated",
+            self.path.display(),
+            self.files,
+            self.updated
+        )
+    }
+}
+
+// `minigrep index DIR...`: builds the index of each directory, or updates
+// the one it has.
+pub fn build_indexes(
+    config: &Config,
+    warn: &mut dyn FnMut(Warning),
+) -> Result<Vec<Indexed>, Box<dyn Error>> {
+    let mut indexed = Vec::new();
+    for path in &config.paths {
+        let root = Path::new(path);
+        if !root.is_dir() {
+            return Err(format!("{} isn't a directory", path).into());
+        }
+        // Every file a search could find, whatever it's filtered by.
+        let files = walk::walk(root, &config.ignore, &GlobFilter::new::<&str>(&[]), warn);
+        let mut index = match Index::open(root) {
+            Ok(index) => index.unwrap_or_default(),
+            // Start again from scratch.
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => Index::default(),
+            Err(e) => return Err(e.into()),
+        };
+        let updated = index.update(root, &files)?;
+        index.save(root)?;
+        indexed.push(Indexed {
+            path: root.to_path_buf(),
+            files: index.len(),
+            updated,
+        });
+    }
+    Ok(indexed)
+}
+
+fn search_input(
+    searcher: &Searcher,
+    matcher: &dyn Matcher,
+    input: &Input,
+    sink: &mut dyn Sink,
+    warn: &mut dyn FnMut(Warning),
+) -> io::Result<Stats> {
+    match input {
+        Input::Stdin => {
+            let stdin = io::stdin();
+            searcher.search_reader(matcher, stdin.lock(), Path::new(STDIN_LABEL), sink)
+        }
+        // Compressed files are searched as if they had been decompressed.
+        Input::File(path) | Input::Walked(path) => {
+            let mut sink = WriteErrors {
+                sink,
+                failed: false,
+            };
+            match searcher.search_path(matcher, path, &mut sink) {
+                // A file that can't be read (or isn't there), or a corrupt
+                // compressed one, is reported and passed over rather than
+                // ending the whole search, as grep does. Failing to write the
+                // output still ends everything.
+                Err(error) if !sink.failed => {
+                    warn(Warning {
+                        path: path.clone(),
+                        error,
+                    });
+                    Ok(Stats::default())
+                }
+                result => result,
+            }
+        }
+    }
+}
+
+// Passes everything on to `sink`, noting whether it failed, so that an
+// error from writing the output can be told apart from one reading the
+// input.
+struct WriteErrors<'a> {
+    sink: &'a mut dyn Sink,
+    failed: bool,
+}
+
+impl WriteErrors<'_> {
+    fn check<T>(&mut self, result: io::Result<T>) -> io::Result<T> {
+        self.failed |= result.is_err();
+        result
+    }
+}
+
+impl Sink for WriteErrors<'_> {
+    fn begin(&mut self, path: &Path) -> io::Result<()> {
+        let result = self.sink.begin(path);
+        self.check(result)
+    }
+
+    fn matched(&mut self, path: &Path, m: &Match) -> io::Result<bool> {
+        let result = self.sink.matched(path, m);
+        self.check(result)
+    }
+
+    fn context(&mut self, path: &Path, m: &Match) -> io::Result<bool> {
+        let result = self.sink.context(path, m);
+        self.check(result)
+    }
+
+    fn context_break(&mut self) -> io::Result<()> {
+        let result = self.sink.context_break();
+        self.check(result)
+    }
+
+    fn binary_data(&mut self, path: &Path, byte_offset: usize) -> io::Result<bool> {
+        let result = self.sink.binary_data(path, byte_offset);
+        self.check(result)
+    }
+
+    fn end(&mut self, path: &Path, stats: &Stats) -> io::Result<()> {
+        let result = self.sink.end(path, stats);
+        self.check(result)
+    }
+}
+
+// `--in-place` and `--dry-run`: replace the matches in each whole file and
+// write it back, or print the diff instead of writing.
+fn rewrite(
+    config: &Config,
+    matcher: &dyn Matcher,
+    inputs: &[Input],
+    warn: &mut dyn FnMut(Warning),
+) -> Result<(), Box<dyn Error>> {
+    let replacement = config.replace.as_deref().unwrap_or_default();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for input in inputs {
+        let (path, contents) = match input {
+            Input::Stdin => {
+                let mut contents = String::new();
+                io::stdin().read_to_string(&mut contents)?;
+                (Path::new(STDIN_LABEL), contents)
+            }
+            Input::File(path) => {
+                let bytes = fs::read(path)?;
+                if Format::detect(&bytes).is_some() {
+                    let message = format!("{}: can't rewrite a compressed file", path.display());
+                    return Err(message.into());
+                }
+                (path.as_path(), String::from_utf8(bytes)?)
+            }
+            // Binary files found in a directory are left alone, as they are
+            // when searching.
+            Input::Walked(path) => match fs::read_to_string(path) {
+                Ok(contents) if !contents.contains('\0') => (path.as_path(), contents),
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => continue,
+                Err(error) => {
+                    warn(Warning {
+                        path: path.clone(),
+                        error,
+                    });
+                    continue;
+                }
+            },
+        };
+        let changes = replace::changes(matcher, &contents, replacement);
+        if changes.is_empty() {
+            continue;
+        }
+        if config.dry_run {
+            write!(out, "{}", replace::unified_diff(path, &contents, &changes))?;
+        } else {
+            let rewritten = replace::apply(&contents, &changes);
+            replace::write_atomically(path, rewritten.as_bytes())?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+// `--watch`: search the files as they are, then keep searching whatever's
+// written to them until killed. Directories are walked again each time
+// round, to pick up files created in them.
+fn watch(
+    config: &Config,
+    matcher: &dyn Matcher,
+    paths: &[String],
+    with_filename: bool,
+    color: bool,
+    warn: &mut dyn FnMut(Warning),
+) -> Result<(), Box<dyn Error>> {
+    let searcher = new_searcher(config);
+    let stdout = io::stdout();
+    let mut output = Output::new(config, stdout.lock(), with_filename, color);
+    let mut watch = Watch::new();
+    // The same directories are walked every time round, but anything that
+    // can't be read is only reported once.
+    let mut reported = HashSet::new();
+    loop {
+        let inputs = collect_inputs(config, paths, &mut |warning: Warning| {
+            if reported.insert(warning.path.clone()) {
+                warn(warning);
+            }
+        })?;
+        for input in inputs {
+            if let Input::File(path) | Input::Walked(path) = input {
+                watch.add(path);
+            }
+        }
+        // A watch never finishes, so there's no summary to total up the
+        // stats for; `--json` is refused along with `--watch`.
+        watch.check(&searcher, matcher, output.sink(), warn)?;
+        output.flush()?;
+        watch.wait()?;
+    }
+}
+
+// What a worker sends back for one file.
+struct FileOutput {
+    // Whether it printed a group of lines.
+    wrote_group: bool,
+    output: Vec<u8>,
+    stats: Stats,
+    warnings: Vec<Warning>,
+}
+
+// Searches every input on a pool of worker threads. Each file's output is
+// buffered separately and written out in the original order, so the result
+// is the same as a sequential run.
+fn search_parallel(
+    config: Config,
+    matcher: Arc<dyn Matcher>,
+    inputs: Vec<Input>,
+    with_filename: bool,
+    color: bool,
+    threads: usize,
+    warn: &mut dyn FnMut(Warning),
+) -> Result<(), Box<dyn Error>> {
+    let config = Arc::new(config);
+    let total = inputs.len();
+    let (tx, rx) = mpsc::channel();
+    let pool = ThreadPool::new(threads);
+    let submit = |(i, input): (usize, Input)| {
+        let config = Arc::clone(&config);
+        let matcher = Arc::clone(&matcher);
+        let tx = tx.clone();
+        pool.execute(move || {
+            // A bug that panics in one file is reported as a problem with
+            // that file, so the output doesn't stop at it.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                let searcher = new_searcher(&config);
+                let mut output = Output::new(&config, Vec::new(), with_filename, color);
+                let mut warnings = Vec::new();
+                let result = search_input(&searcher, &*matcher, &input, output.sink(), &mut |w| {
+                    warnings.push(w)
+                });
+                result.map(|stats| FileOutput {
+                    wrote_group: output.wrote_group(),
+                    output: output.into_inner(),
+                    stats,
+                    warnings,
+                })
+            }));
+            let result = result.unwrap_or_else(|_| {
+                let path = match &input {
+                    Input::Stdin => PathBuf::from(STDIN_LABEL),
+                    Input::File(path) | Input::Walked(path) => path.clone(),
+                };
+                Ok(FileOutput {
+                    wrote_group: false,
+                    output: Vec::new(),
+                    stats: Stats::default(),
+                    warnings: vec![Warning {
+                        path,
+                        error: io::Error::other("the search panicked"),
+                    }],
+                })
+            });
+            // The receiver is only gone if an earlier file failed.
+            let _ = tx.send((i, result));
+        });
+    };
+
+    // Results arrive in whatever order the workers finish; hold on to each
+    // one until everything before it has been written. Only so many files
+    // are searched ahead of the next one to be written, so that one slow
+    // file can't leave the output of all the rest waiting in memory.
+    let ahead = threads * 4;
+    let mut inputs = inputs.into_iter().enumerate();
+    inputs.by_ref().take(ahead).for_each(submit);
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut pending = HashMap::new();
+    let mut next = 0;
+    let mut wrote_group = false;
+    let mut stats = Stats::default();
+    while next < total {
+        // Every task sends its result, and `tx` is still held here, so
+        // this can only block until the next one finishes.
+        let (i, result) = rx.recv().expect("the pool's sender is still open");
+        pending.insert(i, result);
+        while let Some(result) = pending.remove(&next) {
+            let file = result?;
+            for warning in file.warnings {
+                warn(warning);
+            }
+            // Each file had its own printer, so the `--` between files is
+            // added here.
+            if file.wrote_group && wrote_group {
+                writeln!(out, "--")?;
+            }
+            wrote_group |= file.wrote_group;
+            out.write_all(&file.output)?;
+            stats += file.stats;
+            next += 1;
+            if let Some(input) = inputs.next() {
+                submit(input);
+            }
+        }
+    }
+    Output::new(&config, out, with_filename, color).finish(&stats)?;
+    Ok(())
+}
+
+fn build_matcher(config: &Config) -> Result<Box<dyn Matcher>, Box<dyn Error>> {
+    let queries = &config.queries;
+    let matcher: Box<dyn Matcher> = if let Some(max_edits) = config.fuzzy {
+        if queries.len() != 1 {
+            return Err("--fuzzy takes exactly one pattern".into());
+        }
+        let fuzzy = if config.case_sensitive {
+            Fuzzy::new(&queries[0], max_edits)?
+        } else {
+            Fuzzy::case_insensitive(&queries[0], max_edits)?
+        };
+        Box::new(fuzzy)
+    } else if config.query {
+        if queries.len() != 1 {
+            return Err("--query takes exactly one pattern".into());
+        }
+        // `-w` and `-x` apply to each term.
+        let query = Query::new(&queries[0], config.case_sensitive)?.map_terms(|term| {
+            if config.line_regexp {
+                Box::new(WholeLine(term))
+            } else if config.word_regexp {
+                Box::new(WholeWord(term))
+            } else {
+                term
+            }
+        });
+        return Ok(Box::new(query));
+    } else if config.regex && !queries.is_empty() {
+        // Several regexes become one alternation. Each is compiled on its own
+        // first, so that an error points into the pattern that has it.
+        for query in queries {
+            RegexBuilder::new(query).build()?;
+        }
+        let mut pattern = queries
+            .iter()
+            .map(|query| format!("(?:{})", query))
+            .collect::<Vec<_>>()
+            .join("|");
+        // Anchoring the regex, rather than checking its match afterwards,
+        // means `a|ab` still matches the line "ab".
+        if config.line_regexp {
+            pattern = format!("^(?:{})$", pattern);
+        }
+        let re = RegexBuilder::new(&pattern)
+            .case_insensitive(!config.case_sensitive)
+            .build()?;
+        Box::new(re)
+    } else if queries.len() == 1 && config.case_sensitive {
+        Box::new(Literal::new(&queries[0]))
+    } else if queries.len() == 1 {
+        Box::new(CaseInsensitive::new(&queries[0]))
+    } else if config.case_sensitive {
+        // Many literals (or none, which matches nothing) are searched for
+        // in a single pass.
+        Box::new(AhoCorasick::new(queries))
+    } else {
+        Box::new(AhoCorasick::case_insensitive(queries))
+    };
+    // `-x` wins over `-w`, as in grep.
+    let matcher: Box<dyn Matcher> = if config.line_regexp && !config.regex {
+        Box::new(WholeLine(matcher))
+    } else if config.word_regexp && !config.line_regexp {
+        Box::new(WholeWord(matcher))
+    } else {
+        matcher
+    };
+    Ok(matcher)
+}
+
+pub fn search<'a, M: Matcher + ?Sized>(matcher: &M, contents: &'a str) -> Vec<&'a str> {
     // The lifetime indicates that the returned vectro should contain string
     // slices that reference slices of the argument `contents`, not `query`.
+    // `matcher` is either a literal `&str` query or a compiled `Regex`.
 
     // let mut results = Vec::new();
     // for line in contents.lines() {
@@ -64,29 +703,154 @@ pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
     // }
     // results
 
+    // Calling `line.contains(query)` on every line meant starting a new
+    // search at each line break. When a match can't span lines, `scan`
+    // searches the whole of `contents` at once instead, and only the lines
+    // that matched are split out.
+    if matcher.matches_within_lines() {
+        return scan(matcher, contents).map(|(_, line)| line).collect();
+    }
     contents
         .lines()
-        .filter(|line| line.contains(query))
+        .filter(|line| matcher.is_match(line))
         .collect()
 }
 
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
+// Like `search`, but returns where each matching line is and what matched.
+pub fn search_matches<'a, M: Matcher + ?Sized>(matcher: &M, contents: &'a str) -> Vec<Match<'a>> {
+    if matcher.matches_within_lines() {
+        let mut line_number = 1;
+        // Newlines before this offset have been counted into `line_number`.
+        let mut counted = 0;
+        return scan(matcher, contents)
+            .map(|(byte_offset, line)| {
+                line_number += contents[counted..byte_offset]
+                    .bytes()
+                    .filter(|&b| b == b'\n')
+                    .count();
+                counted = byte_offset;
+                Match {
+                    line_number,
+                    byte_offset,
+                    line,
+                    raw: line_bytes(contents, byte_offset),
+                    spans: matcher.find_all(line),
+                    edit_distance: matcher.edit_distance(line),
+                }
+            })
+            .collect();
+    }
+    lines(contents)
+        .enumerate()
+        .filter_map(|(i, (byte_offset, line))| {
+            let spans = matcher.find_all(line);
+            if spans.is_empty() {
+                return None;
+            }
+            Some(Match {
+                line_number: i + 1,
+                byte_offset,
+                line,
+                raw: line_bytes(contents, byte_offset),
+                spans,
+                edit_distance: matcher.edit_distance(line),
+            })
+        })
+        .collect()
+}
 
-    // let mut results = Vec::new();
-    // for line in contents.lines() {
-    //     if line.to_lowercase().contains(&query) {
-    //         results.push(line)
-    //     }
-    // }
-    // results
+// The byte offset and text of each line containing a match, found by
+// searching `contents` as one string. After a match the search skips to the
+// next line, so a line is reported once however many matches it has. Only
+// correct for matchers whose matches never include a `\n`.
+//
+// Lines are found as they're asked for, so a caller that's seen enough can
+// stop without searching the rest.
+pub(crate) fn scan<'a, 'm, M: Matcher + ?Sized>(
+    matcher: &'m M,
+    contents: &'a str,
+) -> Scan<'a, 'm, M> {
+    Scan {
+        matcher,
+        contents,
+        pos: 0,
+    }
+}
 
-    contents
-        .lines()
-        .filter(|line| line.to_lowercase().contains(&query))
+pub(crate) struct Scan<'a, 'm, M: ?Sized> {
+    matcher: &'m M,
+    contents: &'a str,
+    // Always the start of a line.
+    pos: usize,
+}
+
+impl<'a, 'm, M: Matcher + ?Sized> Iterator for Scan<'a, 'm, M> {
+    type Item = (usize, &'a str);
+
+    fn next(&mut self) -> Option<(usize, &'a str)> {
+        let (contents, pos) = (self.contents, self.pos);
+        if pos >= contents.len() {
+            return None;
+        }
+        let (start, _) = self.matcher.find_at(contents, pos)?;
+        let line_start = contents[pos..start]
+            .rfind('\n')
+            .map_or(pos, |i| pos + i + 1);
+        let line_end = contents[start..]
+            .find('\n')
+            .map_or(contents.len(), |i| start + i);
+        if line_start == contents.len() {
+            // An empty match after the final newline, where there's no line.
+            return None;
+        }
+        let line = &contents[line_start..line_end];
+        self.pos = line_end + 1;
+        Some((line_start, line.strip_suffix('\r').unwrap_or(line)))
+    }
+}
+
+// The line starting at `byte_offset`, with its newline if it has one.
+pub(crate) fn line_bytes(contents: &str, byte_offset: usize) -> &[u8] {
+    let rest = &contents.as_bytes()[byte_offset..];
+    match rest.iter().position(|&b| b == b'\n') {
+        Some(i) => &rest[..i + 1],
+        None => rest,
+    }
+}
+
+// Same lines as `str::lines`, each paired with its byte offset in `contents`.
+pub(crate) fn lines(contents: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut offset = 0;
+    contents.split_terminator('\n').map(move |line| {
+        let start = offset;
+        offset += line.len() + 1;
+        (start, line.strip_suffix('\r').unwrap_or(line))
+    })
+}
+
+// The lines that `search_matches` would skip, for `-v`. They carry no spans.
+pub fn search_inverted<'a, M: Matcher + ?Sized>(matcher: &M, contents: &'a str) -> Vec<Match<'a>> {
+    lines(contents)
+        .enumerate()
+        .filter(|(_, (_, line))| !matcher.is_match(line))
+        .map(|(i, (byte_offset, line))| Match {
+            line_number: i + 1,
+            byte_offset,
+            line,
+            raw: line_bytes(contents, byte_offset),
+            spans: Vec::new(),
+            edit_distance: None,
+        })
         .collect()
 }
 
+pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    // Lowercasing every line and calling `contains` allocates per line and
+    // goes wrong when case changes the byte length of a char. Comparing
+    // with Unicode simple case folding avoids both; see `casefold`.
+    search(&CaseInsensitive::new(query), contents)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -118,4 +882,194 @@ Trust me.";
             search_case_insensitive(query, contents)
         );
     }
+
+    #[test]
+    fn whole_word() {
+        let contents = "\
+run
+truncate
+running
+don't run!
+über_run
+runé";
+
+        let m = WholeWord(Literal::new("run"));
+        assert_eq!(vec!["run", "don't run!"], search(&m, contents));
+        let m = WholeWord(CaseInsensitive::new("RUN"));
+        assert_eq!(vec!["run", "don't run!"], search(&m, contents));
+        // A later match on the same line can still be a whole word.
+        assert_eq!(
+            vec![(5, 8)],
+            WholeWord(Literal::new("run")).find_all("rung run")
+        );
+    }
+
+    #[test]
+    fn whole_line() {
+        let contents = "\
+Rust
+Rust:\r
+rust\r
+trust";
+
+        let m = WholeLine(Literal::new("Rust"));
+        assert_eq!(vec!["Rust"], search(&m, contents));
+        let m = WholeLine(CaseInsensitive::new("rust"));
+        assert_eq!(vec!["Rust", "rust"], search(&m, contents));
+        let m = WholeLine(AhoCorasick::new(&["Rust", "Rust:"]));
+        assert_eq!(vec!["Rust", "Rust:"], search(&m, contents));
+    }
+
+    #[test]
+    fn case_insensitive_unicode() {
+        let contents = "\
+ΣΟΦΙΑ
+σοφια
+straße
+STRASSE";
+
+        assert_eq!(
+            vec!["ΣΟΦΙΑ", "σοφια"],
+            search_case_insensitive("Σοφια", contents)
+        );
+        // Simple folding keeps 'ß' as one char, so it doesn't match "SS".
+        assert_eq!(vec!["straße"], search_case_insensitive("STRAßE", contents));
+    }
+
+    #[test]
+    fn match_positions() {
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me, rust.";
+
+        assert_eq!(
+            vec![
+                Match {
+                    line_number: 1,
+                    byte_offset: 0,
+                    line: "Rust:",
+                    raw: b"Rust:\n",
+                    spans: vec![(0, 4)],
+                    edit_distance: None,
+                },
+                Match {
+                    line_number: 4,
+                    byte_offset: 42,
+                    line: "Trust me, rust.",
+                    raw: b"Trust me, rust.",
+                    spans: vec![(1, 5), (10, 14)],
+                    edit_distance: None,
+                },
+            ],
+            search_matches(&CaseInsensitive::new("rust"), contents)
+        );
+    }
+
+    #[test]
+    fn inverted() {
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.";
+
+        let lines: Vec<_> = search_inverted("Rust", contents)
+            .into_iter()
+            .map(|m| (m.line_number, m.line))
+            .collect();
+        assert_eq!(
+            vec![(2, "safe, fast, productive."), (3, "Pick three.")],
+            lines
+        );
+    }
+
+    #[test]
+    fn literal_scan() {
+        let contents = "\
+Rust:
+safe, fast, productive.\r
+Pick three.
+Trust me, rust.";
+
+        let m = Literal::new("st");
+        assert_eq!(
+            vec!["Rust:", "safe, fast, productive.", "Trust me, rust."],
+            search(&m, contents)
+        );
+        let lines: Vec<_> = search_matches(&m, contents)
+            .into_iter()
+            .map(|m| (m.line_number, m.byte_offset, m.spans))
+            .collect();
+        assert_eq!(
+            vec![
+                (1, 0, vec![(2, 4)]),
+                (2, 6, vec![(8, 10)]),
+                (4, 43, vec![(3, 5), (12, 14)]),
+            ],
+            lines
+        );
+        // An empty query matches every line, but not past the last one.
+        assert_eq!(vec!["a", "", "b"], search(&Literal::new(""), "a\n\nb\n"));
+    }
+
+    #[test]
+    fn regex_query() {
+        let re = Regex::new(r"^fn \w+").unwrap();
+        let contents = "\
+fn main() {
+    helper();
+}
+fn helper() {}";
+
+        assert_eq!(vec!["fn main() {", "fn helper() {}"], search(&re, contents));
+    }
+
+    #[test]
+    fn regex_case_insensitive() {
+        let re = RegexBuilder::new("^t?rust")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+        assert_eq!(vec!["Rust:", "Trust me."], search(&re, contents));
+    }
+
+    #[test]
+    fn missing_file_is_a_warning() {
+        let dir = std::env::temp_dir().join(format!("minigrep-inputs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("f1"), "one x\n").unwrap();
+        fs::write(dir.join("f2"), "two x\n").unwrap();
+        let inputs = [
+            Input::File(dir.join("nonexist")),
+            Input::File(dir.join("f1")),
+            Input::File(dir.join("f2")),
+        ];
+
+        let mut lines = Vec::new();
+        let mut warnings = Vec::new();
+        let mut sink = FnSink(|_: &Path, m: &Match| {
+            lines.push(m.line.to_string());
+            Ok(true)
+        });
+        let matcher = Literal::new("x");
+        for input in &inputs {
+            search_input(&Searcher::new(), &matcher, input, &mut sink, &mut |w| {
+                warnings.push(w)
+            })
+            .unwrap();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(vec!["one x", "two x"], lines);
+        assert_eq!(1, warnings.len());
+        assert_eq!(dir.join("nonexist"), warnings[0].path);
+        assert_eq!(io::ErrorKind::NotFound, warnings[0].error.kind());
+    }
 }
@@ -0,0 +1,165 @@
+use crate::Matcher;
+
+// Boyer-Moore-Horspool substring search.
+//
+// The needle is compared against a window of the haystack starting from its
+// last byte. On a mismatch the window slides by a distance looked up from
+// the haystack byte under the end of the window: if that byte doesn't occur
+// in the needle at all, the window can jump past it entirely. For typical
+// queries most of the haystack is skipped over without being looked at.
+//
+// The shift table is built once per query, so one `Literal` is meant to be
+// reused for every line (or a whole buffer) being searched.
+#[derive(Debug, Clone)]
+pub struct Literal {
+    needle: Vec<u8>,
+    // How far to slide the window when its last byte is `b`.
+    shift: [usize; 256],
+    ignore_ascii_case: bool,
+}
+
+impl Literal {
+    pub fn new(needle: &str) -> Literal {
+        Literal::build(needle, false)
+    }
+
+    // Matches ASCII letters in either case; every other byte must match
+    // exactly. Only sound for ASCII needles, which can never match part of a
+    // multi-byte char.
+    pub(crate) fn ascii_case_insensitive(needle: &str) -> Literal {
+        debug_assert!(needle.is_ascii());
+        Literal::build(needle, true)
+    }
+
+    fn build(needle: &str, ignore_ascii_case: bool) -> Literal {
+        let needle = needle.as_bytes().to_vec();
+        let len = needle.len();
+        let mut shift = [len.max(1); 256];
+        // The last byte is left out: if it's the only occurrence, the window
+        // can still slide by the full length.
+        for (i, &b) in needle.iter().enumerate().take(len.saturating_sub(1)) {
+            shift[b as usize] = len - 1 - i;
+            if ignore_ascii_case {
+                shift[b.to_ascii_lowercase() as usize] = len - 1 - i;
+                shift[b.to_ascii_uppercase() as usize] = len - 1 - i;
+            }
+        }
+        Literal {
+            needle,
+            shift,
+            ignore_ascii_case,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.needle.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.needle.is_empty()
+    }
+
+    // Byte offset of the first occurrence at or after `start`.
+    pub fn find(&self, haystack: &[u8], start: usize) -> Option<usize> {
+        let needle = &self.needle[..];
+        match needle.len() {
+            0 => Some(start),
+            // No window to slide; look at the bytes one at a time.
+            1 if !self.ignore_ascii_case => haystack[start..]
+                .iter()
+                .position(|&b| b == needle[0])
+                .map(|i| start + i),
+            1 => {
+                let (lower, upper) = (
+                    needle[0].to_ascii_lowercase(),
+                    needle[0].to_ascii_uppercase(),
+                );
+                haystack[start..]
+                    .iter()
+                    .position(|&b| b == lower || b == upper)
+                    .map(|i| start + i)
+            }
+            // Two copies of the loop, so the comparison is inlined into each.
+            _ if self.ignore_ascii_case => {
+                self.horspool(haystack, start, |a, b| a.eq_ignore_ascii_case(b))
+            }
+            _ => self.horspool(haystack, start, |a, b| a == b),
+        }
+    }
+
+    fn horspool<F>(&self, haystack: &[u8], start: usize, eq: F) -> Option<usize>
+    where
+        F: Fn(&[u8], &[u8]) -> bool,
+    {
+        let needle = &self.needle[..];
+        let len = needle.len();
+        let last = len - 1;
+        let mut i = start;
+        while let Some(window) = haystack.get(i..i + len) {
+            if eq(&window[last..], &needle[last..]) && eq(&window[..last], &needle[..last]) {
+                return Some(i);
+            }
+            i += self.shift[window[last] as usize];
+        }
+        None
+    }
+}
+
+impl Matcher for Literal {
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        // The standard library's search for a single char is hard to beat.
+        if let [b] = self.needle[..] {
+            if b.is_ascii() && !self.ignore_ascii_case {
+                return haystack[start..]
+                    .find(b as char)
+                    .map(|i| (start + i, start + i + 1));
+            }
+        }
+        // A UTF-8 needle found in UTF-8 text always starts and ends on char
+        // boundaries, so the span can be used to slice `haystack`.
+        self.find(haystack.as_bytes(), start)
+            .map(|i| (i, i + self.len()))
+    }
+
+    fn matches_within_lines(&self) -> bool {
+        !self.needle.contains(&b'\n')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds() {
+        let m = Literal::new("duct");
+        assert_eq!(Some((15, 19)), m.find_at("safe, fast, productive.", 0));
+        assert_eq!(None, m.find_at("safe, fast, productive.", 17));
+        assert_eq!(None, m.find_at("duc", 0));
+        assert_eq!(Some((2, 4)), Literal::new("aa").find_at("abaaa", 0));
+        assert_eq!(vec![(2, 4)], Literal::new("aa").find_all("abaaa"));
+        assert_eq!(Some((2, 3)), Literal::new("x").find_at("abx", 0));
+        assert_eq!(Some((1, 1)), Literal::new("").find_at("abx", 1));
+        assert_eq!(Some((1, 6)), Literal::new("ürü").find_at("aürüb", 0));
+    }
+
+    #[test]
+    fn ascii_case_insensitive() {
+        let m = Literal::ascii_case_insensitive("rUsT");
+        assert_eq!(Some((1, 5)), m.find_at("TRUST me", 0));
+        assert_eq!(Some((5, 9)), m.find_at("Réstrust", 0));
+        assert_eq!(None, m.find_at("Rus", 0));
+    }
+
+    #[test]
+    fn finds_bytes_after_invalid_utf8() {
+        let haystack = b"\xff\xfe caf\xe9 x";
+        assert_eq!(Some(8), Literal::new("x").find(haystack, 0));
+        assert_eq!(
+            Some(8),
+            Literal::ascii_case_insensitive("X").find(haystack, 0)
+        );
+        assert_eq!(Some(3), Literal::new("caf").find(haystack, 0));
+        assert_eq!(None, Literal::new("x").find(haystack, 9));
+    }
+}
@@ -1,10 +1,15 @@
 use std::env;
 use std::process;
 
-use minigrep::Config;
+use minigrep::{Config, ConfigError, Warning};
 
 fn main() {
     let config = Config::new(env::args()).unwrap_or_else(|err| { // closure
+        // --help and --version aren't really errors: print and succeed.
+        if let ConfigError::Help | ConfigError::Version = err {
+            println!("{}", err);
+            process::exit(0);
+        }
         eprintln!("Problem parsing arguments: {}", err);
         process::exit(1);
     });
@@ -12,10 +17,31 @@ fn main() {
 
     // Because run returns Result<(), Box<dyn Error>>, we only care about the 
     // error result. Thus, we don't need unwrap_or_else.
-    if let Err(e) = minigrep::run(config) {
+    // Files that couldn't be read are reported as the search goes on, and
+    // only make it fail at the end.
+    let mut failed = false;
+    let mut warn = |warning: Warning| {
+        eprintln!("minigrep: {}", warning);
+        failed = true;
+    };
+    // `minigrep index DIR...` says what it did once it's done; a search
+    // prints its results as it goes.
+    let result = if config.build_index {
+        minigrep::build_indexes(&config, &mut warn).map(|indexed| {
+            for index in indexed {
+                println!("{}", index);
+            }
+        })
+    } else {
+        minigrep::run(config, &mut warn)
+    };
+    if let Err(e) = result {
         eprintln!("Application error: {}", e);
         process::exit(1);
     }
+    if failed {
+        process::exit(1);
+    }
 }
 
 
@@ -0,0 +1,259 @@
+use crate::casefold::fold;
+use crate::literal::Literal;
+
+// A `Matcher` is anything that can find the byte span of a match inside a
+// line. `search` is generic over it, so a plain `&str` query and a compiled
+// `Regex` go through the same code path. Matchers are shared between the
+// worker threads of a parallel search, hence `Send + Sync`.
+pub trait Matcher: Send + Sync {
+    // Returns the `(start, end)` byte span of the first match in `haystack`
+    // that begins at or after `start`.
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)>;
+
+    fn is_match(&self, haystack: &str) -> bool {
+        self.find_at(haystack, 0).is_some()
+    }
+
+    // Every non-overlapping match in `haystack`, left to right. After an
+    // empty match the search resumes one char later so it can't get stuck.
+    fn find_all(&self, haystack: &str) -> Vec<(usize, usize)> {
+        let mut spans = Vec::new();
+        let mut start = 0;
+        while start <= haystack.len() {
+            let (s, e) = match self.find_at(haystack, start) {
+                Some(span) => span,
+                None => break,
+            };
+            spans.push((s, e));
+            start = if e > s {
+                e
+            } else {
+                e + haystack[e..].chars().next().map_or(1, char::len_utf8)
+            };
+        }
+        spans
+    }
+
+    // Whether a match can never include a `\n`. If so, a whole buffer can
+    // be searched in one go and only the lines that matched split out of
+    // it; see `search`.
+    fn matches_within_lines(&self) -> bool {
+        false
+    }
+
+    // For approximate matchers, the fewest edits any match in `haystack`
+    // needed. Exact matchers have nothing to report.
+    fn edit_distance(&self, _haystack: &str) -> Option<usize> {
+        None
+    }
+}
+
+// A literal query is the simplest matcher: plain substring search.
+impl Matcher for str {
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        haystack[start..]
+            .find(self)
+            .map(|i| (start + i, start + i + self.len()))
+    }
+
+    fn matches_within_lines(&self) -> bool {
+        !self.contains('\n')
+    }
+}
+
+impl Matcher for String {
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        self.as_str().find_at(haystack, start)
+    }
+
+    fn matches_within_lines(&self) -> bool {
+        self.as_str().matches_within_lines()
+    }
+}
+
+// A literal query that ignores case, using Unicode simple case folding.
+// Both sides are compared one folded char at a time, so the span returned
+// points into the original haystack even when upper and lower case forms
+// have different UTF-8 lengths.
+//
+// That char-at-a-time comparison is slow, so ASCII queries, by far the most
+// common, are searched for with `Literal` instead.
+pub struct CaseInsensitive {
+    query: Vec<char>,
+    ascii: Option<Literal>,
+}
+
+// The only non-ASCII chars that fold to ASCII: 'ſ' (long s) and 'K' (kelvin
+// sign). An ASCII query with an 's' or a 'k' can match text containing them.
+const FOLDS_TO_ASCII: [char; 2] = ['\u{017F}', '\u{212A}'];
+
+impl CaseInsensitive {
+    pub fn new(query: &str) -> CaseInsensitive {
+        let ascii = if query.is_ascii() {
+            Some(Literal::ascii_case_insensitive(query))
+        } else {
+            None
+        };
+        CaseInsensitive {
+            query: query.chars().map(fold).collect(),
+            ascii,
+        }
+    }
+
+    fn find_folded(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        for (i, _) in haystack[start..].char_indices() {
+            let begin = start + i;
+            let mut chars = haystack[begin..].char_indices();
+            let matched = self
+                .query
+                .iter()
+                .all(|&q| chars.next().is_some_and(|(_, c)| fold(c) == q));
+            if matched {
+                let end = chars.next().map_or(haystack.len(), |(j, _)| begin + j);
+                return Some((begin, end));
+            }
+        }
+        if self.query.is_empty() {
+            return Some((haystack.len(), haystack.len()));
+        }
+        None
+    }
+}
+
+impl Matcher for CaseInsensitive {
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        if let Some(ascii) = &self.ascii {
+            let found = ascii.find_at(haystack, start);
+            // A match that relies on 'ſ' or 'K' would have to start before
+            // the ASCII one, and so would contain one of them before `end`.
+            let end = found.map_or(haystack.len(), |(_, end)| end);
+            let folds = self.query.iter().any(|&c| c == 's' || c == 'k');
+            if !folds || !haystack[start..end].contains(FOLDS_TO_ASCII) {
+                return found;
+            }
+        }
+        self.find_folded(haystack, start)
+    }
+
+    fn matches_within_lines(&self) -> bool {
+        !self.query.contains(&'\n')
+    }
+}
+
+// Lets callers pick a matcher at runtime and still hand it to `search`.
+impl<M: Matcher + ?Sized> Matcher for Box<M> {
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        (**self).find_at(haystack, start)
+    }
+
+    fn matches_within_lines(&self) -> bool {
+        (**self).matches_within_lines()
+    }
+
+    fn edit_distance(&self, haystack: &str) -> Option<usize> {
+        (**self).edit_distance(haystack)
+    }
+}
+
+// `-w`: only matches that aren't part of a bigger word, meaning there's no
+// word char right before or after them.
+//
+// When a match fails that test, the search starts again one char later, so
+// for a regex a shorter match from the same place is never tried.
+pub struct WholeWord<M>(pub M);
+
+impl<M: Matcher> Matcher for WholeWord<M> {
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        let mut pos = start;
+        while let Some((s, e)) = self.0.find_at(haystack, pos) {
+            let before = haystack[..s].chars().next_back();
+            let after = haystack[e..].chars().next();
+            if !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char) {
+                return Some((s, e));
+            }
+            match haystack[s..].chars().next() {
+                Some(c) => pos = s + c.len_utf8(),
+                None => break,
+            }
+        }
+        None
+    }
+
+    fn matches_within_lines(&self) -> bool {
+        self.0.matches_within_lines()
+    }
+}
+
+// `-x`: only a match that is a whole line. Lines end at a `\n` (or `\r\n`)
+// or the end of the haystack, so this also works when `search` hands over a
+// whole buffer instead of one line.
+pub struct WholeLine<M>(pub M);
+
+impl<M: Matcher> Matcher for WholeLine<M> {
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        let mut pos = start;
+        while let Some((s, e)) = self.0.find_at(haystack, pos) {
+            let at_start = s == 0 || haystack[..s].ends_with('\n');
+            let rest = &haystack[e..];
+            let at_end = rest.is_empty() || rest.starts_with('\n') || rest.starts_with("\r\n");
+            if at_start && at_end {
+                return Some((s, e));
+            }
+            // The next place a line could start.
+            match haystack[s..].find('\n') {
+                Some(i) => pos = s + i + 1,
+                None => break,
+            }
+        }
+        None
+    }
+
+    fn matches_within_lines(&self) -> bool {
+        self.0.matches_within_lines()
+    }
+}
+
+// Letters, digits and `_`, in any script; what `\w` and `\b` mean in a regex.
+pub(crate) fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal_find_at() {
+        assert_eq!(Some((4, 6)), "ab".find_at("xyz ab ab", 0));
+        assert_eq!(Some((7, 9)), "ab".find_at("xyz ab ab", 5));
+        assert_eq!(None, "ab".find_at("xyz ab ab", 8));
+        assert_eq!(vec![(4, 6), (7, 9)], "ab".find_all("xyz ab ab"));
+    }
+
+    #[test]
+    fn case_insensitive_find_at() {
+        let m = CaseInsensitive::new("rUsT");
+        assert_eq!(Some((1, 5)), m.find_at("Trust me", 0));
+        assert_eq!(Some((3, 7)), m.find_at("Ünrust", 0));
+        assert_eq!(None, m.find_at("Rus", 0));
+        // Spans point into the original text, so highlighting lines up even
+        // when the match has multi-byte chars.
+        assert_eq!(Some((1, 4)), CaseInsensitive::new("äb").find_at("xÄBc", 0));
+        // 'ſ' (2 bytes) folds to 's' and 'K' (kelvin sign, 3 bytes) to 'k'.
+        assert_eq!(
+            Some((2, 8)),
+            CaseInsensitive::new("sKy").find_at("a ſ\u{212A}y", 0)
+        );
+        assert_eq!(Some((0, 6)), CaseInsensitive::new("ΣΑΣ").find_at("σας", 0));
+        // An ASCII query still finds matches that go through 'ſ' or 'K',
+        // even when a plain ASCII match comes later.
+        assert_eq!(
+            Some((0, 4)),
+            CaseInsensitive::new("ks").find_at("\u{212A}s ks", 0)
+        );
+        assert_eq!(
+            Some((2, 5)),
+            CaseInsensitive::new("as").find_at("ſaſ as", 0)
+        );
+    }
+}
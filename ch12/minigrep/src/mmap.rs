@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::slice;
+
+// A read-only memory map of a whole file, so that it can be searched where
+// it is in the page cache instead of being copied into a buffer first.
+//
+// Only Linux has one here; elsewhere `open` always fails, and the file is
+// read the usual way.
+pub(crate) struct Mmap {
+    ptr: *const u8,
+    len: usize,
+}
+
+// The map is never written to, so it can be read from any thread.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    // Maps all of `file`, which has to be a regular file.
+    //
+    // Safety: the bytes change if the file is written to while it's mapped,
+    // and reading past its end after it's been truncated kills the process
+    // with SIGBUS. That's a risk worth taking for a short-lived search, but
+    // not for a file that's expected to change, like one being `--watch`ed.
+    #[cfg(target_os = "linux")]
+    pub(crate) unsafe fn open(file: &File) -> io::Result<Mmap> {
+        use std::convert::TryFrom;
+        use std::os::unix::io::AsRawFd;
+        use std::ptr;
+
+        let len = file.metadata()?.len();
+        let len = usize::try_from(len).map_err(|_| io::Error::other("file too large to map"))?;
+        // There's nothing to map, and `mmap` refuses a length of 0.
+        if len == 0 {
+            return Ok(Mmap {
+                ptr: ptr::NonNull::dangling().as_ptr(),
+                len: 0,
+            });
+        }
+        let ptr = libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ,
+            libc::MAP_PRIVATE,
+            file.as_raw_fd(),
+            0,
+        );
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // A search reads it once from start to end, so the kernel can read
+        // ahead further and drop pages sooner. Only a hint; if it fails, the
+        // map is as good as it was.
+        libc::madvise(ptr, len, libc::MADV_SEQUENTIAL);
+        Ok(Mmap {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) unsafe fn open(_file: &File) -> io::Result<Mmap> {
+        Err(io::Error::other(
+            "memory maps aren't supported on this platform",
+        ))
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if self.len > 0 {
+            unsafe {
+                libc::munmap(self.ptr as *mut libc::c_void, self.len);
+            }
+        }
+    }
+}
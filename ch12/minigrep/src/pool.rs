@@ -0,0 +1,102 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+type Task = Box<dyn FnOnce() + Send + 'static>;
+
+// A fixed number of worker threads pulling tasks off a shared channel, the
+// same design as `my_server::ThreadPool`. Dropping the pool closes the
+// channel and waits for every queued task to finish.
+pub struct ThreadPool {
+    threads: Vec<thread::JoinHandle<()>>,
+    // Only `None` while the pool is being dropped.
+    tx: Option<Sender<Task>>,
+}
+
+impl ThreadPool {
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0);
+
+        let (tx, rx) = mpsc::channel::<Task>();
+        let rx = Arc::new(Mutex::new(rx));
+
+        let mut threads = Vec::with_capacity(size);
+
+        for _ in 0..size {
+            let thread_rx = Arc::clone(&rx);
+
+            // The lock is released as soon as `recv` returns, so other
+            // workers can pick up tasks while this one runs.
+            threads.push(thread::spawn(move || loop {
+                let task = thread_rx.lock().unwrap().recv();
+                match task {
+                    // A task that panics takes only itself down, not the
+                    // worker; the panic has already been reported.
+                    Ok(f) => {
+                        let _ = panic::catch_unwind(AssertUnwindSafe(f));
+                    }
+                    // The sender is gone: the pool is shutting down.
+                    Err(_) => break,
+                }
+            }))
+        }
+
+        ThreadPool {
+            threads,
+            tx: Some(tx),
+        }
+    }
+
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + 'static + Send,
+    {
+        self.tx.as_ref().unwrap().send(Box::new(f)).unwrap();
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        drop(self.tx.take());
+        for thread in self.threads.drain(..) {
+            thread.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_every_task() {
+        let (tx, rx) = mpsc::channel();
+        {
+            let pool = ThreadPool::new(3);
+            for i in 0..10 {
+                let tx = tx.clone();
+                pool.execute(move || tx.send(i).unwrap());
+            }
+        }
+        drop(tx);
+        let mut results: Vec<i32> = rx.iter().collect();
+        results.sort();
+        assert_eq!((0..10).collect::<Vec<_>>(), results);
+    }
+
+    #[test]
+    fn survives_a_panicking_task() {
+        let (tx, rx) = mpsc::channel();
+        {
+            let pool = ThreadPool::new(1);
+            pool.execute(|| panic!("task failed"));
+            pool.execute(move || tx.send(1).unwrap());
+        }
+        assert_eq!(vec![1], rx.iter().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,351 @@
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::replace::replace_spans;
+use crate::searcher::Stats;
+use crate::{Match, OutputMode, Sink};
+
+// ANSI SGR parameters for each part of the output, e.g. "1;31" for bold
+// red. The defaults are the same as GNU grep's.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Colors {
+    pub matched: String,
+    pub path: String,
+    pub line_number: String,
+    pub separator: String,
+}
+
+impl Default for Colors {
+    fn default() -> Colors {
+        Colors {
+            matched: "1;31".to_string(),
+            path: "35".to_string(),
+            line_number: "32".to_string(),
+            separator: "36".to_string(),
+        }
+    }
+}
+
+// Writes what a `Searcher` finds the way grep does:
+//
+//   path:12:matching line
+//   path-13-context line
+//   --
+//
+// `:` marks a matching line and `-` a context line. The output mode picks
+// between the lines themselves, just the matched parts (`-o`), a count per
+// file (`-c`) or the names of files (`-l` and `-L`).
+pub struct Printer<W: Write> {
+    out: W,
+    line_number: bool,
+    // Prefix lines and counts with the file they came from.
+    with_filename: bool,
+    // `None` prints plain text.
+    colors: Option<Colors>,
+    output: OutputMode,
+    // `--replace`: what to print in place of each match.
+    replace: Option<String>,
+    // What ends each line (or record) printed.
+    terminator: Vec<u8>,
+    // Whether the current file has turned out to be binary.
+    binary: bool,
+    // Whether a group has been written yet, across all files, so we know
+    // when a `--` separator is needed.
+    wrote_group: bool,
+}
+
+impl<W: Write> Printer<W> {
+    pub fn new(out: W, line_number: bool) -> Printer<W> {
+        Printer {
+            out,
+            line_number,
+            with_filename: false,
+            colors: None,
+            output: OutputMode::Lines,
+            replace: None,
+            terminator: b"\n".to_vec(),
+            binary: false,
+            wrote_group: false,
+        }
+    }
+
+    pub fn with_filename(mut self, with_filename: bool) -> Printer<W> {
+        self.with_filename = with_filename;
+        self
+    }
+
+    // Highlights matches, file names, line numbers and separators with ANSI
+    // escapes.
+    pub fn with_colors(mut self, colors: Colors) -> Printer<W> {
+        self.colors = Some(colors);
+        self
+    }
+
+    pub fn with_output(mut self, output: OutputMode) -> Printer<W> {
+        self.output = output;
+        self
+    }
+
+    pub fn with_replace(mut self, replace: Option<String>) -> Printer<W> {
+        self.replace = replace;
+        self
+    }
+
+    // With `-p` or `-z`, records are printed with the separator they had in
+    // the input, rather than a newline.
+    pub fn with_terminator(mut self, terminator: &[u8]) -> Printer<W> {
+        self.terminator = terminator.to_vec();
+        self
+    }
+
+    pub fn wrote_group(&self) -> bool {
+        self.wrote_group
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    // Highlights `m.spans`, the byte ranges in `m.line` that matched.
+    fn print_line(&mut self, path: &Path, m: &Match) -> io::Result<()> {
+        // `--fuzzy` puts the edit distance first, so that the output can be
+        // piped through `sort -n` to see the closest matches first.
+        if let Some(distance) = m.edit_distance {
+            paint(&mut self.out, None, distance)?;
+            self.print_separator(':')?;
+        }
+        self.print_prefix(path, m.line_number, ':')?;
+        match &self.colors {
+            Some(colors) => {
+                let mut last = 0;
+                for &(start, end) in &m.spans {
+                    if start == end {
+                        continue;
+                    }
+                    write!(self.out, "{}", &m.line[last..start])?;
+                    paint(&mut self.out, Some(&colors.matched), &m.line[start..end])?;
+                    last = end;
+                }
+                write!(self.out, "{}", &m.line[last..])?;
+            }
+            None => write!(self.out, "{}", m.line)?,
+        }
+        self.out.write_all(&self.terminator)
+    }
+
+    // `-o`: each match on a line of its own. With `--fuzzy`, each gets the
+    // edit distance of the closest match on its line.
+    fn print_only_matching(&mut self, path: &Path, m: &Match) -> io::Result<()> {
+        for &(start, end) in &m.spans {
+            if start == end {
+                continue;
+            }
+            let text = match &self.replace {
+                Some(replacement) => replacement.clone(),
+                None => m.line[start..end].to_string(),
+            };
+            let part = Match {
+                byte_offset: m.byte_offset + start,
+                line: &text,
+                spans: vec![(0, text.len())],
+                ..m.clone()
+            };
+            self.print_line(path, &part)?;
+        }
+        Ok(())
+    }
+
+    // `-c`: the number of selected lines in a file.
+    fn print_count(&mut self, path: &Path, count: usize) -> io::Result<()> {
+        if self.with_filename {
+            self.print_path_prefix(path, ':')?;
+        }
+        writeln!(self.out, "{}", count)
+    }
+
+    // `-l` and `-L`: just the name of a file.
+    fn print_path(&mut self, path: &Path) -> io::Result<()> {
+        let style = self.colors.as_ref().map(|c| c.path.as_str());
+        paint(&mut self.out, style, path.display())?;
+        writeln!(self.out)
+    }
+
+    fn print_prefix(&mut self, path: &Path, line_number: usize, sep: char) -> io::Result<()> {
+        if self.with_filename {
+            self.print_path_prefix(path, sep)?;
+        }
+        if self.line_number {
+            let style = self.colors.as_ref().map(|c| c.line_number.as_str());
+            paint(&mut self.out, style, line_number)?;
+            self.print_separator(sep)?;
+        }
+        Ok(())
+    }
+
+    fn print_path_prefix(&mut self, path: &Path, sep: char) -> io::Result<()> {
+        let style = self.colors.as_ref().map(|c| c.path.as_str());
+        paint(&mut self.out, style, path.display())?;
+        self.print_separator(sep)
+    }
+
+    fn print_separator(&mut self, sep: char) -> io::Result<()> {
+        let style = self.colors.as_ref().map(|c| c.separator.as_str());
+        paint(&mut self.out, style, sep)
+    }
+}
+
+impl<W: Write> Sink for Printer<W> {
+    fn begin(&mut self, _path: &Path) -> io::Result<()> {
+        self.binary = false;
+        Ok(())
+    }
+
+    fn matched(&mut self, path: &Path, m: &Match) -> io::Result<bool> {
+        match self.output {
+            // One match is all `-l` and `-L` need to know.
+            OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch => return Ok(false),
+            OutputMode::Count => {}
+            // A binary file's lines aren't printed (they're likely to be
+            // garbage, or mess up the terminal), just that it matched.
+            _ if self.binary => {
+                writeln!(self.out, "Binary file {} matches", path.display())?;
+                return Ok(false);
+            }
+            OutputMode::OnlyMatching => self.print_only_matching(path, m)?,
+            OutputMode::Lines => match &self.replace {
+                Some(replacement) => {
+                    let (line, spans) = replace_spans(m.line, &m.spans, replacement);
+                    let replaced = Match {
+                        line: &line,
+                        spans,
+                        ..m.clone()
+                    };
+                    self.print_line(path, &replaced)?;
+                }
+                None => self.print_line(path, m)?,
+            },
+        }
+        Ok(true)
+    }
+
+    fn context(&mut self, path: &Path, m: &Match) -> io::Result<bool> {
+        if self.output == OutputMode::Lines {
+            self.print_prefix(path, m.line_number, '-')?;
+            write!(self.out, "{}", m.line)?;
+            self.out.write_all(&self.terminator)?;
+        }
+        Ok(true)
+    }
+
+    // Starts a new group of matching and context lines, writing a `--`
+    // separator if this isn't the first group.
+    fn context_break(&mut self) -> io::Result<()> {
+        if self.wrote_group {
+            let style = self.colors.as_ref().map(|c| c.separator.as_str());
+            paint(&mut self.out, style, "--")?;
+            writeln!(self.out)?;
+        }
+        self.wrote_group = true;
+        Ok(())
+    }
+
+    fn binary_data(&mut self, _path: &Path, _byte_offset: usize) -> io::Result<bool> {
+        self.binary = true;
+        Ok(true)
+    }
+
+    fn end(&mut self, path: &Path, stats: &Stats) -> io::Result<()> {
+        match self.output {
+            OutputMode::FilesWithMatches if stats.matched_lines > 0 => self.print_path(path),
+            OutputMode::FilesWithoutMatch if stats.matched_lines == 0 => self.print_path(path),
+            OutputMode::Count => self.print_count(path, stats.matched_lines),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Writes `text`, wrapped in an ANSI escape for `style` if there is one.
+fn paint<W: Write>(out: &mut W, style: Option<&str>, text: impl Display) -> io::Result<()> {
+    match style {
+        Some(style) => write!(out, "\x1b[{}m{}\x1b[0m", style, text),
+        None => write!(out, "{}", text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(line_number: usize, line: &str, spans: Vec<(usize, usize)>) -> Match<'_> {
+        Match {
+            line_number,
+            byte_offset: 0,
+            line,
+            raw: line.as_bytes(),
+            spans,
+            edit_distance: None,
+        }
+    }
+
+    #[test]
+    fn prefixes() {
+        let mut printer = Printer::new(Vec::new(), true).with_filename(true);
+        let path = Path::new("poem.txt");
+        printer.context_break().unwrap();
+        printer
+            .matched(path, &line(2, "b x", vec![(2, 3)]))
+            .unwrap();
+        printer.context(path, &line(3, "c", vec![])).unwrap();
+        printer.context_break().unwrap();
+        printer.matched(path, &line(5, "x", vec![(0, 1)])).unwrap();
+        assert_eq!(
+            "poem.txt:2:b x\npoem.txt-3-c\n--\npoem.txt:5:x\n",
+            String::from_utf8(printer.into_inner()).unwrap()
+        );
+
+        let mut printer = Printer::new(Vec::new(), true).with_output(OutputMode::Count);
+        let stats = Stats {
+            matched_lines: 4,
+            ..Stats::default()
+        };
+        assert!(printer.matched(path, &line(9, "x", vec![(0, 1)])).unwrap());
+        printer.end(path, &stats).unwrap();
+        assert_eq!("4\n", String::from_utf8(printer.into_inner()).unwrap());
+    }
+
+    #[test]
+    fn colors() {
+        let mut printer = Printer::new(Vec::new(), true)
+            .with_filename(true)
+            .with_colors(Colors::default());
+        printer
+            .matched(Path::new("f"), &line(7, "a xx b x", vec![(2, 4), (7, 8)]))
+            .unwrap();
+        assert_eq!(
+            "\x1b[35mf\x1b[0m\x1b[36m:\x1b[0m\x1b[32m7\x1b[0m\x1b[36m:\x1b[0m\
+             a \x1b[1;31mxx\x1b[0m b \x1b[1;31mx\x1b[0m\n",
+            String::from_utf8(printer.into_inner()).unwrap()
+        );
+    }
+
+    #[test]
+    fn binary() {
+        let mut printer = Printer::new(Vec::new(), false);
+        let path = Path::new("f");
+        printer.begin(path).unwrap();
+        printer.binary_data(path, 3).unwrap();
+        assert!(!printer.matched(path, &line(2, "x", vec![(0, 1)])).unwrap());
+        // The next file starts out as text again.
+        printer.begin(path).unwrap();
+        printer.matched(path, &line(1, "x", vec![(0, 1)])).unwrap();
+        assert_eq!(
+            "Binary file f matches\nx\n",
+            String::from_utf8(printer.into_inner()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use crate::matcher::{CaseInsensitive, Matcher};
+use crate::Literal;
+
+// A boolean query over plain-text terms:
+//
+//   error AND (timeout OR "connection refused") AND NOT retry
+//
+// `NOT` binds tightest, then `AND`, then `OR`, and parentheses group.
+// Terms next to each other with nothing in between are ANDed, as in a
+// search engine, so `error timeout` is `error AND timeout`. The operators
+// have to be in capitals; a lowercase `and` is just a word to look for.
+//
+// A term is a word, or a "quoted phrase" for one with spaces, parentheses
+// or an operator's name in it; `\"` and `\\` escape inside the quotes.
+//
+// A line (or record) matches if the query is true for it. Only the terms
+// that aren't under a `NOT` can be highlighted, since the others aren't on
+// a matching line.
+pub struct Query {
+    expr: Expr,
+}
+
+enum Expr {
+    Term(Box<dyn Matcher>),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, haystack: &str) -> bool {
+        match self {
+            Expr::Term(matcher) => matcher.is_match(haystack),
+            Expr::Not(expr) => !expr.eval(haystack),
+            Expr::And(exprs) => exprs.iter().all(|expr| expr.eval(haystack)),
+            Expr::Or(exprs) => exprs.iter().any(|expr| expr.eval(haystack)),
+        }
+    }
+
+    fn map_terms<F>(self, f: &mut F) -> Expr
+    where
+        F: FnMut(Box<dyn Matcher>) -> Box<dyn Matcher>,
+    {
+        match self {
+            Expr::Term(matcher) => Expr::Term(f(matcher)),
+            Expr::Not(expr) => Expr::Not(Box::new(expr.map_terms(f))),
+            Expr::And(exprs) => Expr::And(exprs.into_iter().map(|e| e.map_terms(f)).collect()),
+            Expr::Or(exprs) => Expr::Or(exprs.into_iter().map(|e| e.map_terms(f)).collect()),
+        }
+    }
+
+    fn positive_spans(&self, haystack: &str, spans: &mut Vec<(usize, usize)>) {
+        match self {
+            Expr::Term(matcher) => spans.extend(matcher.find_all(haystack)),
+            Expr::Not(_) => {}
+            Expr::And(exprs) | Expr::Or(exprs) => {
+                for expr in exprs {
+                    expr.positive_spans(haystack, spans);
+                }
+            }
+        }
+    }
+}
+
+impl Query {
+    // Terms are matched as plain text like `search` does, or ignoring case
+    // like `search_case_insensitive`.
+    pub fn new(query: &str, case_sensitive: bool) -> Result<Query, ParseError> {
+        Query::parse(query, |term| -> Box<dyn Matcher> {
+            if case_sensitive {
+                Box::new(Literal::new(term))
+            } else {
+                Box::new(CaseInsensitive::new(term))
+            }
+        })
+    }
+
+    // Parses `query`, making a matcher for each term with `term`.
+    pub fn parse<F>(query: &str, term: F) -> Result<Query, ParseError>
+    where
+        F: FnMut(&str) -> Box<dyn Matcher>,
+    {
+        let tokens = tokenize(query)?;
+        if tokens.is_empty() {
+            return Err(ParseError::Empty);
+        }
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            term,
+        };
+        let expr = parser.or()?;
+        // Everything else would have been taken as another term to AND.
+        if let Some(&(_, at)) = parser.tokens.get(parser.pos) {
+            return Err(ParseError::UnmatchedParen(at));
+        }
+        Ok(Query { expr })
+    }
+
+    // The same query, with each term's matcher passed through `f`, e.g. to
+    // make every term match only whole words.
+    pub fn map_terms<F>(self, mut f: F) -> Query
+    where
+        F: FnMut(Box<dyn Matcher>) -> Box<dyn Matcher>,
+    {
+        Query {
+            expr: self.expr.map_terms(&mut f),
+        }
+    }
+}
+
+impl Matcher for Query {
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        self.find_all(haystack)
+            .into_iter()
+            .find(|&(s, _)| s >= start)
+    }
+
+    fn is_match(&self, haystack: &str) -> bool {
+        self.expr.eval(haystack)
+    }
+
+    // The matches of every term not under a `NOT`, on a line the query
+    // matches. Where they overlap they're merged, so the spans stay in
+    // order and never overlap, like any other matcher's.
+    fn find_all(&self, haystack: &str) -> Vec<(usize, usize)> {
+        if !self.is_match(haystack) {
+            return Vec::new();
+        }
+        let mut spans = Vec::new();
+        self.expr.positive_spans(haystack, &mut spans);
+        spans.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(spans.len());
+        for (s, e) in spans {
+            match merged.last_mut() {
+                Some(last) if s < last.1 => last.1 = last.1.max(e),
+                _ => merged.push((s, e)),
+            }
+        }
+        merged
+    }
+}
+
+// What's wrong with a query. Offsets count chars from the start of it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    Empty,
+    // The query ended where a term was expected, as in `error AND`.
+    UnexpectedEnd,
+    // An operator or `)` where a term was expected.
+    ExpectedTerm(String, usize),
+    UnclosedParen(usize),
+    UnmatchedParen(usize),
+    UnterminatedQuote(usize),
+    EmptyPhrase(usize),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "the query is empty"),
+            ParseError::UnexpectedEnd => write!(f, "query ends where a term was expected"),
+            ParseError::ExpectedTerm(found, i) => {
+                write!(f, "expected a term at offset {}, found '{}'", i, found)
+            }
+            ParseError::UnclosedParen(i) => write!(f, "unclosed '(' at offset {}", i),
+            ParseError::UnmatchedParen(i) => write!(f, "unmatched ')' at offset {}", i),
+            ParseError::UnterminatedQuote(i) => write!(f, "unterminated quote at offset {}", i),
+            ParseError::EmptyPhrase(i) => write!(f, "empty phrase at offset {}", i),
+        }
+    }
+}
+
+impl StdError for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Term(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl Token {
+    // How the token was written, for error messages.
+    fn text(&self) -> &str {
+        match self {
+            Token::Term(term) => term,
+            Token::And => "AND",
+            Token::Or => "OR",
+            Token::Not => "NOT",
+            Token::Open => "(",
+            Token::Close => ")",
+        }
+    }
+}
+
+// Each token, and the char offset it starts at.
+fn tokenize(query: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        match chars[i] {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => {
+                i += 1;
+                tokens.push((Token::Open, start));
+            }
+            ')' => {
+                i += 1;
+                tokens.push((Token::Close, start));
+            }
+            '"' => {
+                i += 1;
+                let mut phrase = String::new();
+                loop {
+                    match chars.get(i) {
+                        None => return Err(ParseError::UnterminatedQuote(start)),
+                        Some('"') => break,
+                        Some('\\') if matches!(chars.get(i + 1), Some('"') | Some('\\')) => {
+                            phrase.push(chars[i + 1]);
+                            i += 2;
+                        }
+                        Some(&c) => {
+                            phrase.push(c);
+                            i += 1;
+                        }
+                    }
+                }
+                i += 1;
+                if phrase.is_empty() {
+                    return Err(ParseError::EmptyPhrase(start));
+                }
+                tokens.push((Token::Term(phrase), start));
+            }
+            _ => {
+                while i < chars.len() && !chars[i].is_whitespace() && !"()\"".contains(chars[i]) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                let token = match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Term(word),
+                };
+                tokens.push((token, start));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// A recursive descent parser, one method per level of precedence.
+struct Parser<F> {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    term: F,
+}
+
+impl<F: FnMut(&str) -> Box<dyn Matcher>> Parser<F> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut exprs = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            exprs.push(self.and()?);
+        }
+        Ok(one_or(exprs, Expr::Or))
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut exprs = vec![self.not()?];
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.pos += 1,
+                // Nothing in between: an implicit AND.
+                Some(Token::Term(_)) | Some(Token::Not) | Some(Token::Open) => {}
+                _ => break,
+            }
+            exprs.push(self.not()?);
+        }
+        Ok(one_or(exprs, Expr::And))
+    }
+
+    fn not(&mut self) -> Result<Expr, ParseError> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let (token, at) = match self.tokens.get(self.pos) {
+            Some(token) => token.clone(),
+            None => return Err(ParseError::UnexpectedEnd),
+        };
+        self.pos += 1;
+        match token {
+            Token::Term(term) => Ok(Expr::Term((self.term)(&term))),
+            Token::Open => {
+                let expr = self.or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(ParseError::UnclosedParen(at));
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            token => Err(ParseError::ExpectedTerm(token.text().to_string(), at)),
+        }
+    }
+}
+
+fn one_or(mut exprs: Vec<Expr>, combine: fn(Vec<Expr>) -> Expr) -> Expr {
+    if exprs.len() == 1 {
+        exprs.remove(0)
+    } else {
+        combine(exprs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluates() {
+        let query = Query::new("error AND (timeout OR refused) AND NOT retry", true).unwrap();
+        assert!(query.is_match("error: connection refused"));
+        assert!(query.is_match("timeout error"));
+        assert!(!query.is_match("error: timeout, will retry"));
+        assert!(!query.is_match("timeout"));
+
+        // An implicit AND, quoted phrases, and NOT binding tighter.
+        let query = Query::new("\"disk full\" host NOT db OR panic", true).unwrap();
+        assert!(query.is_match("host 3: disk full"));
+        assert!(!query.is_match("host db: disk full"));
+        assert!(query.is_match("panic"));
+
+        let query = Query::new("ERROR and", false).unwrap();
+        assert!(query.is_match("error AND more"));
+        assert!(!query.is_match("error"));
+    }
+
+    #[test]
+    fn maps_terms() {
+        let query = Query::new("cat AND NOT dog", true)
+            .unwrap()
+            .map_terms(|term| Box::new(crate::WholeWord(term)));
+        assert!(query.is_match("a cat and hotdogs"));
+        assert!(!query.is_match("concatenate"));
+        assert!(!query.is_match("cat, dog"));
+    }
+
+    #[test]
+    fn highlights_positive_terms() {
+        let query = Query::new("b OR ab OR NOT c", true).unwrap();
+        assert_eq!(vec![(0, 2), (3, 4)], query.find_all("ab b"));
+        assert_eq!(Vec::<(usize, usize)>::new(), query.find_all("c"));
+    }
+
+    #[test]
+    fn errors() {
+        let error = |query: &str| Query::new(query, true).err().unwrap();
+        assert_eq!(ParseError::Empty, error("  "));
+        assert_eq!(ParseError::UnexpectedEnd, error("a AND"));
+        assert_eq!(
+            ParseError::ExpectedTerm("OR".to_string(), 6),
+            error("a AND OR b")
+        );
+        assert_eq!(ParseError::UnclosedParen(2), error("a (b OR c"));
+        assert_eq!(ParseError::UnmatchedParen(1), error("a) b"));
+        assert_eq!(ParseError::UnterminatedQuote(0), error("\"a b"));
+        assert_eq!(ParseError::EmptyPhrase(2), error("a \"\""));
+        assert_eq!(
+            "expected a term at offset 1, found ')'",
+            error("()").to_string()
+        );
+    }
+}
@@ -0,0 +1,914 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::casefold::fold;
+use crate::matcher::{is_word_char, Matcher};
+
+// A small regular expression engine. The pattern is parsed into an AST,
+// compiled into a list of instructions, and run by a Pike VM: all possible
+// threads advance through the input in lock step, so matching is linear in
+// the length of the line and never backtracks.
+//
+// Supported syntax:
+//   literals, `.`, `^`, `$`, `[abc]`, `[^a-z]`, `(...)`, `(?:...)`, `a|b`,
+//   `*`, `+`, `?`, `{n}`, `{n,}`, `{n,m}` (add `?` for the lazy version),
+//   `\d \D \w \W \s \S \b \B`, `\n \t \r` and escaped punctuation.
+
+// Upper bound on `{n,m}` counts so a typo can't blow up the program size.
+const MAX_REPEAT: u32 = 1000;
+// Upper bound on the whole program, since nested counts multiply:
+// `(a{1000}){1000}` would be a million instructions.
+const MAX_PROGRAM: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    UnexpectedEnd,
+    UnclosedGroup(usize),
+    UnmatchedParen(usize),
+    UnclosedClass(usize),
+    InvalidRange(char, char),
+    InvalidEscape(char),
+    MissingRepeatOperand(usize),
+    RepeatTooLarge(u32),
+    TooLarge,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnexpectedEnd => write!(f, "regex ends with a dangling escape"),
+            Error::UnclosedGroup(i) => write!(f, "unclosed group opened at offset {}", i),
+            Error::UnmatchedParen(i) => write!(f, "unmatched ')' at offset {}", i),
+            Error::UnclosedClass(i) => write!(f, "unclosed character class at offset {}", i),
+            Error::InvalidRange(a, b) => write!(f, "invalid class range {}-{}", a, b),
+            Error::InvalidEscape(c) => write!(f, "unknown escape sequence \\{}", c),
+            Error::MissingRepeatOperand(i) => {
+                write!(
+                    f,
+                    "repetition operator at offset {} has nothing to repeat",
+                    i
+                )
+            }
+            Error::RepeatTooLarge(n) => {
+                write!(
+                    f,
+                    "repetition count {} exceeds the limit of {}",
+                    n, MAX_REPEAT
+                )
+            }
+            Error::TooLarge => {
+                write!(
+                    f,
+                    "regex compiles to more than {} instructions",
+                    MAX_PROGRAM
+                )
+            }
+        }
+    }
+}
+
+impl StdError for Error {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Perl {
+    Digit,
+    Word,
+    Space,
+}
+
+impl Perl {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Perl::Digit => c.is_ascii_digit(),
+            Perl::Word => is_word_char(c),
+            Perl::Space => c.is_whitespace(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Range(char, char),
+    // The bool is true for the negated form, e.g. `\D`.
+    Perl(Perl, bool),
+}
+
+// Case-insensitive classes precompute the folded form of every char in
+// ranges up to this size. Bigger ranges (e.g. `[^\x00-\x7f]`) only get the
+// cheaper check of the folded input char.
+const MAX_FOLDED_RANGE: u32 = 0x2000;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Class {
+    negated: bool,
+    items: Vec<ClassItem>,
+    // Sorted folded forms of the chars in `items`, for case-insensitive
+    // matching. Empty until `fold_ranges` is called.
+    folded: Vec<char>,
+}
+
+impl Class {
+    fn contains(&self, c: char) -> bool {
+        self.items.iter().any(|item| match *item {
+            ClassItem::Range(lo, hi) => lo <= c && c <= hi,
+            ClassItem::Perl(p, negated) => p.matches(c) != negated,
+        })
+    }
+
+    // `c` is in the class ignoring case if some char of the class folds to
+    // the same char as `c`.
+    fn matches(&self, c: char, case_insensitive: bool) -> bool {
+        let found = self.contains(c)
+            || (case_insensitive && {
+                let folded = fold(c);
+                self.contains(folded) || self.folded.binary_search(&folded).is_ok()
+            });
+        found != self.negated
+    }
+
+    fn fold_ranges(&mut self) {
+        let mut folded = Vec::new();
+        for item in &self.items {
+            if let ClassItem::Range(lo, hi) = *item {
+                if hi as u32 - lo as u32 <= MAX_FOLDED_RANGE {
+                    folded.extend((lo..=hi).map(fold).filter(|&f| !self.contains(f)));
+                }
+            }
+        }
+        folded.sort_unstable();
+        folded.dedup();
+        self.folded = folded;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Assertion {
+    StartLine,
+    EndLine,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+impl Assertion {
+    fn holds(self, haystack: &str, pos: usize) -> bool {
+        let prev = haystack[..pos].chars().next_back();
+        let next = haystack[pos..].chars().next();
+        match self {
+            Assertion::StartLine => prev.is_none_or(|c| c == '\n'),
+            Assertion::EndLine => next.is_none_or(|c| c == '\n'),
+            Assertion::WordBoundary => {
+                prev.is_some_and(is_word_char) != next.is_some_and(is_word_char)
+            }
+            Assertion::NotWordBoundary => {
+                prev.is_some_and(is_word_char) == next.is_some_and(is_word_char)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+impl Node {
+    // How many instructions `Compiler::compile` turns this into, saturating
+    // rather than overflowing for absurd nestings.
+    fn program_size(&self) -> usize {
+        match self {
+            Node::Empty => 0,
+            Node::Char(_) | Node::Any | Node::Class(_) | Node::Assert(_) => 1,
+            Node::Concat(nodes) => nodes
+                .iter()
+                .fold(0, |size, n| size.saturating_add(n.program_size())),
+            // A split before and a jump after every branch but the last.
+            Node::Alternate(branches) => branches
+                .iter()
+                .fold(2 * (branches.len().saturating_sub(1)), |size, n| {
+                    size.saturating_add(n.program_size())
+                }),
+            Node::Repeat { node, min, max, .. } => {
+                let size = node.program_size();
+                let required = size.saturating_mul(*min as usize);
+                let optional = match max {
+                    None => size.saturating_add(2),
+                    Some(max) => size
+                        .saturating_add(1)
+                        .saturating_mul(max.saturating_sub(*min) as usize),
+                };
+                required.saturating_add(optional)
+            }
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(pattern: &str) -> Parser {
+        Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse(mut self) -> Result<Node, Error> {
+        let node = self.parse_alternate()?;
+        match self.peek() {
+            Some(')') => Err(Error::UnmatchedParen(self.pos)),
+            _ => Ok(node),
+        }
+    }
+
+    fn parse_alternate(&mut self) -> Result<Node, Error> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.eat('|') {
+            branches.push(self.parse_concat()?);
+        }
+        if branches.len() == 1 {
+            Ok(branches.pop().unwrap())
+        } else {
+            Ok(Node::Alternate(branches))
+        }
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, Error> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_repeat(atom)?);
+        }
+        match nodes.len() {
+            0 => Ok(Node::Empty),
+            1 => Ok(nodes.pop().unwrap()),
+            _ => Ok(Node::Concat(nodes)),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, Error> {
+        let start = self.pos;
+        let c = self.next().ok_or(Error::UnexpectedEnd)?;
+        match c {
+            '(' => {
+                // `(?:...)` and `(...)` behave the same: we only report the
+                // span of the whole match, not of sub-groups.
+                if self.peek() == Some('?') && self.chars.get(self.pos + 1) == Some(&':') {
+                    self.pos += 2;
+                }
+                let inner = self.parse_alternate()?;
+                if !self.eat(')') {
+                    return Err(Error::UnclosedGroup(start));
+                }
+                Ok(inner)
+            }
+            '[' => self.parse_class(start),
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Assert(Assertion::StartLine)),
+            '$' => Ok(Node::Assert(Assertion::EndLine)),
+            '*' | '+' | '?' => Err(Error::MissingRepeatOperand(start)),
+            '\\' => self.parse_escape(),
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    fn parse_escape(&mut self) -> Result<Node, Error> {
+        let c = self.next().ok_or(Error::UnexpectedEnd)?;
+        if let Some(item) = perl_item(c) {
+            return Ok(Node::Class(Class {
+                negated: false,
+                items: vec![item],
+                folded: Vec::new(),
+            }));
+        }
+        let node = match c {
+            'b' => Node::Assert(Assertion::WordBoundary),
+            'B' => Node::Assert(Assertion::NotWordBoundary),
+            _ => Node::Char(escaped_char(c)?),
+        };
+        Ok(node)
+    }
+
+    fn parse_class(&mut self, start: usize) -> Result<Node, Error> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+        // A `]` right after the opening bracket is a literal.
+        let mut first = true;
+        loop {
+            let c = self.next().ok_or(Error::UnclosedClass(start))?;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let lo = if c == '\\' {
+                let e = self.next().ok_or(Error::UnclosedClass(start))?;
+                if let Some(item) = perl_item(e) {
+                    items.push(item);
+                    continue;
+                }
+                escaped_char(e)?
+            } else {
+                c
+            };
+
+            // `a-z` is a range, but a trailing `-` is a literal.
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&n| n != ']') {
+                self.pos += 1;
+                let mut hi = self.next().ok_or(Error::UnclosedClass(start))?;
+                if hi == '\\' {
+                    hi = escaped_char(self.next().ok_or(Error::UnclosedClass(start))?)?;
+                }
+                if hi < lo {
+                    return Err(Error::InvalidRange(lo, hi));
+                }
+                items.push(ClassItem::Range(lo, hi));
+            } else {
+                items.push(ClassItem::Range(lo, lo));
+            }
+        }
+        Ok(Node::Class(Class {
+            negated,
+            items,
+            folded: Vec::new(),
+        }))
+    }
+
+    fn parse_repeat(&mut self, mut atom: Node) -> Result<Node, Error> {
+        loop {
+            let start = self.pos;
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => match self.parse_counts()? {
+                    Some(counts) => counts,
+                    None => return Ok(atom),
+                },
+                _ => return Ok(atom),
+            };
+            // `parse_counts` has already consumed the braces.
+            if self.pos == start {
+                self.pos += 1;
+            }
+            if let Node::Assert(_) = atom {
+                return Err(Error::MissingRepeatOperand(start));
+            }
+            let greedy = !self.eat('?');
+            atom = Node::Repeat {
+                node: Box::new(atom),
+                min,
+                max,
+                greedy,
+            };
+        }
+    }
+
+    // Parses `{n}`, `{n,}` or `{n,m}`. A `{` that doesn't form a valid
+    // counted repetition is left alone and parsed as a literal.
+    fn parse_counts(&mut self) -> Result<Option<(u32, Option<u32>)>, Error> {
+        let save = self.pos;
+        self.pos += 1;
+        let min = self.parse_number();
+        let max = if self.eat(',') {
+            self.parse_number()
+        } else {
+            min
+        };
+        match (min, self.eat('}')) {
+            (Some(min), true) => {
+                for &n in [Some(min), max].iter().flatten() {
+                    if n > MAX_REPEAT {
+                        return Err(Error::RepeatTooLarge(n));
+                    }
+                }
+                if let Some(max) = max {
+                    if max < min {
+                        self.pos = save;
+                        return Ok(None);
+                    }
+                }
+                Ok(Some((min, max)))
+            }
+            _ => {
+                self.pos = save;
+                Ok(None)
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return None;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        // Overflowing counts are clamped so they trip the MAX_REPEAT check.
+        Some(digits.parse().unwrap_or(u32::MAX))
+    }
+}
+
+// `\d`, `\w`, `\s` and their negations, usable inside or outside `[...]`.
+fn perl_item(c: char) -> Option<ClassItem> {
+    let item = match c {
+        'd' => ClassItem::Perl(Perl::Digit, false),
+        'D' => ClassItem::Perl(Perl::Digit, true),
+        'w' => ClassItem::Perl(Perl::Word, false),
+        'W' => ClassItem::Perl(Perl::Word, true),
+        's' => ClassItem::Perl(Perl::Space, false),
+        'S' => ClassItem::Perl(Perl::Space, true),
+        _ => return None,
+    };
+    Some(item)
+}
+
+fn escaped_char(c: char) -> Result<char, Error> {
+    match c {
+        'n' => Ok('\n'),
+        't' => Ok('\t'),
+        'r' => Ok('\r'),
+        c if c.is_alphanumeric() => Err(Error::InvalidEscape(c)),
+        c => Ok(c),
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Assert(Assertion),
+    // Try the first target before the second; that order is what makes
+    // greedy and lazy repetition differ.
+    Split(usize, usize),
+    Jmp(usize),
+    Match,
+}
+
+struct Compiler {
+    prog: Vec<Inst>,
+    case_insensitive: bool,
+}
+
+impl Compiler {
+    fn compile(&mut self, node: &Node) {
+        match node {
+            Node::Empty => {}
+            Node::Char(c) => {
+                let c = if self.case_insensitive { fold(*c) } else { *c };
+                self.prog.push(Inst::Char(c));
+            }
+            Node::Any => self.prog.push(Inst::Any),
+            Node::Class(class) => {
+                let mut class = class.clone();
+                if self.case_insensitive {
+                    class.fold_ranges();
+                }
+                self.prog.push(Inst::Class(class));
+            }
+            Node::Assert(a) => self.prog.push(Inst::Assert(*a)),
+            Node::Concat(nodes) => {
+                for n in nodes {
+                    self.compile(n);
+                }
+            }
+            Node::Alternate(branches) => {
+                // split L1, next; L1: a; jmp end; next: split L2, next; ...
+                let mut jumps = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    if i + 1 < branches.len() {
+                        let split = self.push_placeholder();
+                        self.compile(branch);
+                        jumps.push(self.push_placeholder());
+                        let next = self.prog.len();
+                        self.prog[split] = Inst::Split(split + 1, next);
+                    } else {
+                        self.compile(branch);
+                    }
+                }
+                let end = self.prog.len();
+                for j in jumps {
+                    self.prog[j] = Inst::Jmp(end);
+                }
+            }
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.compile(node);
+                }
+                match max {
+                    None => {
+                        // L: split body, end; body; jmp L; end:
+                        let split = self.push_placeholder();
+                        self.compile(node);
+                        self.prog.push(Inst::Jmp(split));
+                        let end = self.prog.len();
+                        self.prog[split] = self.split(split + 1, end, *greedy);
+                    }
+                    Some(max) => {
+                        // Each optional copy may be skipped straight to the end.
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.push_placeholder());
+                            self.compile(node);
+                        }
+                        let end = self.prog.len();
+                        for s in splits {
+                            self.prog[s] = self.split(s + 1, end, *greedy);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn split(&self, body: usize, skip: usize, greedy: bool) -> Inst {
+        if greedy {
+            Inst::Split(body, skip)
+        } else {
+            Inst::Split(skip, body)
+        }
+    }
+
+    fn push_placeholder(&mut self) -> usize {
+        self.prog.push(Inst::Match);
+        self.prog.len() - 1
+    }
+}
+
+// The set of threads alive at one input position, kept in priority order.
+// Each thread is an instruction plus the offset where its match started.
+// `seen` stops the same instruction from being visited twice at one position.
+#[derive(Debug)]
+struct Threads {
+    list: Vec<(usize, usize)>,
+    seen: Vec<bool>,
+    visited: Vec<usize>,
+}
+
+impl Threads {
+    fn new(size: usize) -> Threads {
+        Threads {
+            list: Vec::with_capacity(size),
+            seen: vec![false; size],
+            visited: Vec::with_capacity(size),
+        }
+    }
+
+    // Returns false if `pc` was already visited.
+    fn visit(&mut self, pc: usize) -> bool {
+        if self.seen[pc] {
+            return false;
+        }
+        self.seen[pc] = true;
+        self.visited.push(pc);
+        true
+    }
+
+    fn clear(&mut self) {
+        for &pc in &self.visited {
+            self.seen[pc] = false;
+        }
+        self.visited.clear();
+        self.list.clear();
+    }
+}
+
+// Everything a search needs besides the program, sized for it. It's kept
+// between searches, so a line doesn't cost an allocation.
+#[derive(Debug)]
+struct Scratch {
+    current: Threads,
+    next: Threads,
+    stack: Vec<usize>,
+}
+
+// A `Scratch` for the next search to use. A search on another thread at the
+// same time makes its own.
+#[derive(Debug, Default)]
+struct ScratchCache(Mutex<Option<Scratch>>);
+
+impl ScratchCache {
+    fn take(&self, size: usize) -> Scratch {
+        let cached = self.0.lock().ok().and_then(|mut scratch| scratch.take());
+        cached.unwrap_or_else(|| Scratch {
+            current: Threads::new(size),
+            next: Threads::new(size),
+            stack: Vec::new(),
+        })
+    }
+
+    fn put(&self, scratch: Scratch) {
+        if let Ok(mut cached) = self.0.lock() {
+            *cached = Some(scratch);
+        }
+    }
+}
+
+impl Clone for ScratchCache {
+    fn clone(&self) -> ScratchCache {
+        ScratchCache::default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    pattern: String,
+    prog: Vec<Inst>,
+    case_insensitive: bool,
+    scratch: ScratchCache,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, Error> {
+        RegexBuilder::new(pattern).build()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    // Runs the program over `haystack` from `start`, with threads from
+    // `scratch`.
+    fn run(&self, scratch: &mut Scratch, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        let Scratch {
+            current,
+            next,
+            stack,
+        } = scratch;
+        let mut matched = None;
+        let mut pos = start;
+
+        loop {
+            // A new thread starting here has the lowest priority, so an
+            // earlier (leftmost) start always wins.
+            if matched.is_none() {
+                self.add_thread(current, stack, 0, haystack, pos, pos);
+            }
+            if current.list.is_empty() && matched.is_some() {
+                break;
+            }
+
+            let c = haystack[pos..].chars().next();
+            let next_pos = pos + c.map_or(0, char::len_utf8);
+            for i in 0..current.list.len() {
+                let (pc, thread_start) = current.list[i];
+                let inst = &self.prog[pc];
+                if let Inst::Match = inst {
+                    // Lower-priority threads can't beat this match.
+                    matched = Some((thread_start, pos));
+                    break;
+                }
+                if let Some(c) = c {
+                    if self.char_matches(inst, c) {
+                        self.add_thread(next, stack, pc + 1, haystack, next_pos, thread_start);
+                    }
+                }
+            }
+
+            if c.is_none() {
+                break;
+            }
+            pos = next_pos;
+            std::mem::swap(current, next);
+            next.clear();
+        }
+        matched
+    }
+
+    // Follows every `Split`, `Jmp` and `Assert` from `pc` and queues the
+    // instructions that consume input, preserving priority order.
+    fn add_thread(
+        &self,
+        threads: &mut Threads,
+        stack: &mut Vec<usize>,
+        pc: usize,
+        haystack: &str,
+        pos: usize,
+        start: usize,
+    ) {
+        stack.push(pc);
+        while let Some(pc) = stack.pop() {
+            if !threads.visit(pc) {
+                continue;
+            }
+            match self.prog[pc] {
+                Inst::Jmp(target) => stack.push(target),
+                Inst::Split(first, second) => {
+                    stack.push(second);
+                    stack.push(first);
+                }
+                Inst::Assert(a) => {
+                    if a.holds(haystack, pos) {
+                        stack.push(pc + 1);
+                    }
+                }
+                _ => threads.list.push((pc, start)),
+            }
+        }
+    }
+
+    fn char_matches(&self, inst: &Inst, c: char) -> bool {
+        match inst {
+            Inst::Char(expected) => {
+                if self.case_insensitive {
+                    *expected == fold(c)
+                } else {
+                    *expected == c
+                }
+            }
+            Inst::Any => c != '\n',
+            Inst::Class(class) => class.matches(c, self.case_insensitive),
+            _ => false,
+        }
+    }
+}
+
+impl Matcher for Regex {
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        let mut scratch = self.scratch.take(self.prog.len());
+        let matched = self.run(&mut scratch, haystack, start);
+        scratch.current.clear();
+        scratch.next.clear();
+        self.scratch.put(scratch);
+        matched
+    }
+}
+
+pub struct RegexBuilder {
+    pattern: String,
+    case_insensitive: bool,
+}
+
+impl RegexBuilder {
+    pub fn new(pattern: &str) -> RegexBuilder {
+        RegexBuilder {
+            pattern: pattern.to_string(),
+            case_insensitive: false,
+        }
+    }
+
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut RegexBuilder {
+        self.case_insensitive = yes;
+        self
+    }
+
+    pub fn build(&self) -> Result<Regex, Error> {
+        let ast = Parser::new(&self.pattern).parse()?;
+        if ast.program_size() >= MAX_PROGRAM {
+            return Err(Error::TooLarge);
+        }
+        let mut compiler = Compiler {
+            prog: Vec::new(),
+            case_insensitive: self.case_insensitive,
+        };
+        compiler.compile(&ast);
+        compiler.prog.push(Inst::Match);
+        Ok(Regex {
+            pattern: self.pattern.clone(),
+            prog: compiler.prog,
+            case_insensitive: self.case_insensitive,
+            scratch: ScratchCache::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, haystack: &str) -> Option<(usize, usize)> {
+        Regex::new(pattern).unwrap().find_at(haystack, 0)
+    }
+
+    #[test]
+    fn literals_and_classes() {
+        assert_eq!(Some((2, 5)), find("c.e", "abcde"));
+        assert_eq!(Some((3, 6)), find("[0-9]+", "abc123def"));
+        assert_eq!(Some((0, 3)), find("[^ ]+", "foo bar"));
+        assert_eq!(Some((4, 7)), find(r"\d\d\d", "tel 555-1234"));
+        assert_eq!(None, find("x", "abc"));
+    }
+
+    #[test]
+    fn anchors_and_boundaries() {
+        assert_eq!(Some((0, 7)), find(r"^fn \w+", "fn main() {"));
+        assert_eq!(None, find(r"^fn \w+", "    fn helper()"));
+        assert_eq!(Some((4, 7)), find(r"\brun\b", "let run = 1"));
+        assert_eq!(None, find(r"\brun\b", "truncate running"));
+        assert_eq!(Some((4, 7)), find("end$", "the end"));
+    }
+
+    #[test]
+    fn leftmost_first_alternation_and_repetition() {
+        assert_eq!(Some((0, 3)), find("foo|foobar", "foobar"));
+        assert_eq!(Some((0, 6)), find("a.*b", "axbxxb"));
+        assert_eq!(Some((0, 3)), find("a.*?b", "axbxxb"));
+        assert_eq!(Some((0, 4)), find("a{2,3}b?", "aaab"));
+        assert_eq!(Some((0, 3)), find("x{1}y{0,}z", "xyz"));
+        assert_eq!(Some((1, 5)), find("a{,}", "xa{,}"));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let re = RegexBuilder::new("r[a-u]st")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        assert_eq!(Some((1, 5)), re.find_at("TRUST", 0));
+
+        // Folding applies to classes too: 'ſ' folds to 's', 'Σ' and 'ς' to 'σ'.
+        let re = RegexBuilder::new("[a-z]+ [σ]")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        assert_eq!(Some((0, 6)), re.find_at("ſt Σ", 0));
+        assert_eq!(Some((0, 5)), re.find_at("AB ς", 0));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Error::UnclosedGroup(0), Regex::new("(ab").unwrap_err());
+        assert_eq!(Error::UnmatchedParen(2), Regex::new("ab)").unwrap_err());
+        assert_eq!(Error::UnclosedClass(0), Regex::new("[ab").unwrap_err());
+        assert_eq!(
+            Error::MissingRepeatOperand(0),
+            Regex::new("*a").unwrap_err()
+        );
+        assert_eq!(Error::InvalidEscape('q'), Regex::new(r"\q").unwrap_err());
+        assert_eq!(
+            Error::InvalidRange('z', 'a'),
+            Regex::new("[z-a]").unwrap_err()
+        );
+        assert_eq!(Error::TooLarge, Regex::new("(a{1000}){1000}").unwrap_err());
+        assert_eq!(
+            Error::TooLarge,
+            Regex::new("((a{1000}){1000}){1000}").unwrap_err()
+        );
+    }
+
+    #[test]
+    fn program_size() {
+        for pattern in ["a|bc|(?:d)", "x{2,5}y*?", "^(ab|c)+[0-9]{3}$", ""] {
+            let regex = Regex::new(pattern).unwrap();
+            let ast = Parser::new(pattern).parse().unwrap();
+            // One more for the final `Match`.
+            assert_eq!(regex.prog.len(), ast.program_size() + 1, "{}", pattern);
+        }
+        assert!(Regex::new("(a{1000}){90}").is_ok());
+    }
+
+    #[test]
+    fn reuses_scratch_space() {
+        let re = Regex::new("b+").unwrap();
+        assert_eq!(Some((1, 3)), re.find_at("abba", 0));
+        assert_eq!(Some((3, 4)), re.find_at("aaab", 0));
+        assert_eq!(None, re.find_at("abba", 3));
+    }
+}
@@ -0,0 +1,397 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+
+use crate::{lines, search_matches, Matcher};
+
+// Lines of unchanged context around each hunk of a diff, as in `diff -u`.
+const CONTEXT: usize = 3;
+
+// `line` with every span in `spans` replaced by `replacement`, and where
+// each replacement ended up in the new line, for highlighting.
+pub fn replace_spans(
+    line: &str,
+    spans: &[(usize, usize)],
+    replacement: &str,
+) -> (String, Vec<(usize, usize)>) {
+    let mut replaced = String::with_capacity(line.len());
+    let mut new_spans = Vec::with_capacity(spans.len());
+    let mut last = 0;
+    for &(start, end) in spans {
+        replaced.push_str(&line[last..start]);
+        new_spans.push((replaced.len(), replaced.len() + replacement.len()));
+        replaced.push_str(replacement);
+        last = end;
+    }
+    replaced.push_str(&line[last..]);
+    (replaced, new_spans)
+}
+
+// One line that a replacement changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change<'a> {
+    pub line_number: usize,
+    // Byte offset of the start of the line within the searched contents.
+    pub byte_offset: usize,
+    pub old: &'a str,
+    pub new: String,
+}
+
+// What replacing every match in `contents` would change, found with
+// `search_matches` so the query means exactly what it does when searching.
+pub fn changes<'a, M: Matcher + ?Sized>(
+    matcher: &M,
+    contents: &'a str,
+    replacement: &str,
+) -> Vec<Change<'a>> {
+    search_matches(matcher, contents)
+        .into_iter()
+        .map(|m| Change {
+            line_number: m.line_number,
+            byte_offset: m.byte_offset,
+            old: m.line,
+            new: replace_spans(m.line, &m.spans, replacement).0,
+        })
+        .filter(|change| change.old != change.new)
+        .collect()
+}
+
+// `contents` with `changes` made. Line endings are left alone.
+pub fn apply(contents: &str, changes: &[Change]) -> String {
+    let mut result = String::with_capacity(contents.len());
+    let mut last = 0;
+    for change in changes {
+        result.push_str(&contents[last..change.byte_offset]);
+        result.push_str(&change.new);
+        last = change.byte_offset + change.old.len();
+    }
+    result.push_str(&contents[last..]);
+    result
+}
+
+// A unified diff of `changes` to `contents`, as `diff -u` or `git diff`
+// would print it, for `patch -p1` or `git apply`. Every change replaces one
+// line (with one or more lines, if the replacement has a newline in it), so
+// the two sides can be lined up without a general diff algorithm.
+pub fn unified_diff(path: &Path, contents: &str, changes: &[Change]) -> String {
+    // Each line, and whatever ended it: "\n", "\r\n", or nothing at all
+    // for a last line with no newline.
+    let old: Vec<(&str, &str)> = lines(contents)
+        .map(|(start, line)| {
+            let rest = &contents[start + line.len()..];
+            let ending = if rest.starts_with("\r\n") {
+                "\r\n"
+            } else if rest.starts_with('\n') {
+                "\n"
+            } else {
+                ""
+            };
+            (line, ending)
+        })
+        .collect();
+    let mut diff = match diff_path(path) {
+        Some(path) => format!("--- a/{}\n+++ b/{}\n", path, path),
+        None => format!("--- {}\n+++ {}\n", path.display(), path.display()),
+    };
+    // How many more lines the new file has than the old one so far.
+    let mut offset: isize = 0;
+
+    let mut rest = changes;
+    while let Some(first) = rest.first() {
+        // A hunk takes in every following change whose context touches or
+        // overlaps the previous one's.
+        let mut len = 1;
+        while len < rest.len()
+            && rest[len].line_number - rest[len - 1].line_number <= 2 * CONTEXT + 1
+        {
+            len += 1;
+        }
+        let (hunk, remaining) = rest.split_at(len);
+        rest = remaining;
+
+        let start = first.line_number.saturating_sub(CONTEXT).max(1);
+        let end = (hunk[len - 1].line_number + CONTEXT).min(old.len());
+        let mut body = String::new();
+        let mut new_len = 0;
+        // A run of changed lines is shown as all of its old lines, then all
+        // of its new ones.
+        let mut added = String::new();
+        let mut changed = hunk.iter().peekable();
+        for (n, &(line, ending)) in old.iter().enumerate().take(end).skip(start - 1) {
+            match changed.next_if(|change| change.line_number == n + 1) {
+                Some(change) => {
+                    push_line(&mut body, '-', line, ending);
+                    // The replacement's own newlines end all but its last
+                    // line, which keeps the old line's ending.
+                    let mut new_lines = change.new.split('\n').peekable();
+                    while let Some(new_line) = new_lines.next() {
+                        let ending = if new_lines.peek().is_some() {
+                            "\n"
+                        } else {
+                            ending
+                        };
+                        push_line(&mut added, '+', new_line, ending);
+                        new_len += 1;
+                    }
+                }
+                None => {
+                    body.push_str(&added);
+                    added.clear();
+                    push_line(&mut body, ' ', line, ending);
+                    new_len += 1;
+                }
+            }
+        }
+        body.push_str(&added);
+        let old_len = end + 1 - start;
+        diff.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            start,
+            old_len,
+            start as isize + offset,
+            new_len
+        ));
+        diff.push_str(&body);
+        offset += new_len as isize - old_len as isize;
+    }
+    diff
+}
+
+// One line of a diff. A line with no newline at the end has to be marked
+// as such, or `patch` would add one.
+fn push_line(diff: &mut String, prefix: char, line: &str, ending: &str) {
+    diff.push(prefix);
+    diff.push_str(line);
+    if ending.is_empty() {
+        diff.push_str("\n\\ No newline at end of file\n");
+    } else {
+        diff.push_str(ending);
+    }
+}
+
+// `path` as a diff header names it after the `a/` or `b/`: relative, with
+// no `.` in it. An absolute path outside the current directory can't be
+// put that way, and is left as it is.
+fn diff_path(path: &Path) -> Option<String> {
+    let relative = if path.is_absolute() {
+        path.strip_prefix(env::current_dir().ok()?).ok()?
+    } else {
+        path
+    };
+    let parts: Vec<_> = relative
+        .components()
+        .filter(|part| *part != Component::CurDir)
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect();
+    Some(parts.join("/"))
+}
+
+// Replaces the file at `path` with `contents`, all at once: the new
+// contents go to a temporary file in the same directory, which is then
+// renamed over the original. Anyone reading the file sees either the old
+// version or the new one, never half of each, and a failure part way
+// through leaves the original untouched. A file that doesn't exist yet is
+// created.
+//
+// A symlink is followed, and the file it points to is replaced, keeping
+// the link. So are the file's owner and permissions, as far as we're
+// allowed to set them.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let path = &resolve(path)?;
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+    let temp = path.with_file_name(format!(
+        ".{}.minigrep-{}.tmp",
+        name.to_string_lossy(),
+        process::id()
+    ));
+    let result = (|| {
+        let mut file = File::create(&temp)?;
+        file.write_all(contents)?;
+        match fs::metadata(path) {
+            Ok(metadata) => {
+                // Changing the owner can clear the setuid and setgid bits,
+                // so that's done before the permissions are copied.
+                set_owner(&file, &metadata);
+                file.set_permissions(metadata.permissions())?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+// Where `path` really is, following any symlinks, even to a file that
+// doesn't exist yet.
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    // As many links as Linux follows before giving up.
+    for _ in 0..40 {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let target = fs::read_link(&path)?;
+                // A relative target is relative to the link's directory.
+                path = match path.parent() {
+                    Some(dir) => dir.join(target),
+                    None => target,
+                };
+            }
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(path),
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::other(format!(
+        "{}: too many levels of symbolic links",
+        path.display()
+    )))
+}
+
+// Gives `file` the owner and group in `metadata`. Only root can give a file
+// away, but anyone can set the group to one they're in; if neither works,
+// the new file stays ours, as it would with `sed -i`.
+#[cfg(unix)]
+fn set_owner(file: &File, metadata: &fs::Metadata) {
+    use std::os::unix::fs::{fchown, MetadataExt};
+
+    if fchown(file, Some(metadata.uid()), Some(metadata.gid())).is_err() {
+        let _ = fchown(file, None, Some(metadata.gid()));
+    }
+}
+
+#[cfg(not(unix))]
+fn set_owner(_file: &File, _metadata: &fs::Metadata) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AhoCorasick;
+    use std::env;
+
+    #[test]
+    fn replaces() {
+        let (line, spans) = replace_spans("a xx b x", &[(2, 4), (7, 8)], "yyy");
+        assert_eq!("a yyy b yyy", line);
+        assert_eq!(vec![(2, 5), (8, 11)], spans);
+
+        let contents = "one\r\ntwo one\nthree\n";
+        let changes = changes("one", contents, "1");
+        assert_eq!(
+            vec![(1, "1".to_string()), (2, "two 1".to_string())],
+            changes
+                .iter()
+                .map(|c| (c.line_number, c.new.clone()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!("1\r\ntwo 1\nthree\n", apply(contents, &changes));
+    }
+
+    #[test]
+    fn diff() {
+        let contents: String = (1..=12).map(|n| format!("line {}\n", n)).collect();
+        let matcher = AhoCorasick::new(&["line 2", "line 11", "line 12"]);
+        let changes = changes(&matcher, &contents, "LINE\nTWO");
+        assert_eq!(
+            "\
+--- a/f.txt
++++ b/f.txt
+@@ -1,5 +1,6 @@
+ line 1
+-line 2
++LINE
++TWO
+ line 3
+ line 4
+ line 5
+@@ -8,5 +9,7 @@
+ line 8
+ line 9
+ line 10
+-line 11
+-line 12
++LINE
++TWO
++LINE
++TWO
+",
+            unified_diff(Path::new("f.txt"), &contents, &changes)
+        );
+    }
+
+    #[test]
+    fn diff_line_endings() {
+        let contents = "one\r\ntwo\r\nlast one";
+        let changes = changes("one", contents, "1\r\n2");
+        assert_eq!(
+            "\
+--- /abs/f.txt
++++ /abs/f.txt
+@@ -1,3 +1,5 @@
+-one\r
++1\r
++2\r
+ two\r
+-last one
+\\ No newline at end of file
++last 1\r
++2
+\\ No newline at end of file
+",
+            unified_diff(Path::new("/abs/f.txt"), contents, &changes)
+        );
+    }
+
+    #[test]
+    fn diff_paths() {
+        assert_eq!(
+            Some("d/f.txt".to_string()),
+            diff_path(Path::new("./d/./f.txt"))
+        );
+        let inside = env::current_dir().unwrap().join("d/f.txt");
+        assert_eq!(Some("d/f.txt".to_string()), diff_path(&inside));
+        assert_eq!(None, diff_path(Path::new("/abs/f.txt")));
+    }
+
+    #[test]
+    fn writes_atomically() {
+        let path = env::temp_dir().join(format!("minigrep-replace-{}.txt", process::id()));
+        fs::write(&path, "old").unwrap();
+        write_atomically(&path, b"new").unwrap();
+        assert_eq!("new", fs::read_to_string(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_through_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = env::temp_dir().join(format!("minigrep-replace-link-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        fs::write(&target, "old").unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o640)).unwrap();
+        symlink("target.txt", &link).unwrap();
+
+        write_atomically(&link, b"new").unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!("new", fs::read_to_string(&target).unwrap());
+        let mode = fs::metadata(&target).unwrap().permissions().mode();
+        assert_eq!(0o640, mode & 0o777);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,999 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::ops::AddAssign;
+use std::path::Path;
+use std::str;
+use std::time::Duration;
+
+use crate::decompress::{self, Format};
+use crate::encoding::{self, decode_line, UTF8_BOM};
+use crate::mmap::Mmap;
+use crate::{BinaryFiles, Match, Matcher, MmapChoice, Records, Sink};
+
+// How big a file has to be before `MmapChoice::Auto` maps it. Searching in
+// place skips quickly over lines that don't match, but does a bit more work
+// for each one that does, and every map costs a couple of system calls. For
+// smaller files it's only worth it when matches are rare; from here up it
+// comes out ahead even when nearly every line matches. See `cargo bench`.
+const MMAP_THRESHOLD: u64 = 1 << 20;
+
+// How long a file has to have been left alone before `MmapChoice::Auto`
+// maps it. One that was written to more recently than this, like a log,
+// may well be written to again, or truncated, during the search.
+const MMAP_SETTLED: Duration = Duration::from_secs(10);
+
+// How stdin is named in output, as in grep.
+pub const STDIN_LABEL: &str = "(standard input)";
+
+// Counts for one search, or added up over a whole run.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    // Files (or stdin) searched.
+    pub searches: usize,
+    pub searches_with_match: usize,
+    pub bytes_searched: usize,
+    // Selected lines; with `-v` these are the ones that didn't match.
+    pub matched_lines: usize,
+    // Individual matches within the selected lines.
+    pub matches: usize,
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, other: Stats) {
+        self.searches += other.searches;
+        self.searches_with_match += other.searches_with_match;
+        self.bytes_searched += other.bytes_searched;
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+    }
+}
+
+// Finds the selected lines of an input and hands them to a `Sink`, along
+// with any context lines around them:
+//
+//   let searcher = Searcher::new().with_context(1, 1);
+//   let stats = searcher.search_reader(&matcher, reader, path, &mut sink)?;
+//
+// Which output they make is up to the sink; `Printer` writes them the way
+// grep does.
+#[derive(Debug, Clone, Default)]
+pub struct Searcher {
+    invert_match: bool,
+    before_context: usize,
+    after_context: usize,
+    binary_files: BinaryFiles,
+    records: Records,
+    multiline: bool,
+    mmap: MmapChoice,
+}
+
+impl Searcher {
+    pub fn new() -> Searcher {
+        Searcher::default()
+    }
+
+    // `-v`: select the lines that don't match.
+    pub fn with_invert_match(mut self, invert_match: bool) -> Searcher {
+        self.invert_match = invert_match;
+        self
+    }
+
+    // How many lines before and after each selected line are passed to the
+    // sink as context.
+    pub fn with_context(mut self, before: usize, after: usize) -> Searcher {
+        self.before_context = before;
+        self.after_context = after;
+        self
+    }
+
+    pub fn with_binary_files(mut self, binary_files: BinaryFiles) -> Searcher {
+        self.binary_files = binary_files;
+        self
+    }
+
+    // What's searched and reported at a time: lines, or longer records.
+    pub fn with_records(mut self, records: Records) -> Searcher {
+        self.records = records;
+        self
+    }
+
+    // `-U`: let a match span lines.
+    pub fn with_multiline(mut self, multiline: bool) -> Searcher {
+        self.multiline = multiline;
+        self
+    }
+
+    // Whether `search_path` reads files through a memory map.
+    pub fn with_mmap(mut self, mmap: MmapChoice) -> Searcher {
+        self.mmap = mmap;
+        self
+    }
+
+    // Searches the file at `path`, decompressing it first if it's
+    // compressed.
+    //
+    // A big enough regular file is memory-mapped, and if it's plain UTF-8
+    // text it's searched in place, the way `search` searches a string,
+    // without copying it a line at a time into a buffer. Anything else, or
+    // a file that can't be mapped, is read with `search_reader`.
+    pub fn search_path<M, S>(&self, matcher: &M, path: &Path, sink: &mut S) -> io::Result<Stats>
+    where
+        M: Matcher + ?Sized,
+        S: Sink + ?Sized,
+    {
+        if let Some(map) = self.map(path)? {
+            if Format::detect(&map).is_none() {
+                return self.search_slice(matcher, &map, path, sink);
+            }
+        }
+        let reader = decompress::open(path)?;
+        self.search_reader(matcher, reader, path, sink)
+    }
+
+    // Maps the file at `path` if it should be, going by `with_mmap`.
+    fn map(&self, path: &Path) -> io::Result<Option<Mmap>> {
+        if self.mmap == MmapChoice::Never {
+            return Ok(None);
+        }
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        // Pipes and devices can't be mapped, or not usefully.
+        if !metadata.is_file() {
+            return Ok(None);
+        }
+        if self.mmap == MmapChoice::Auto {
+            let settled = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age >= MMAP_SETTLED);
+            if metadata.len() < MMAP_THRESHOLD || !settled {
+                return Ok(None);
+            }
+        }
+        // Safety: nothing stops another process changing the file while
+        // the map is borrowed as a `&[u8]`. If it's written to, the bytes
+        // change under the search; if it's truncated, reading the pages
+        // past its new end kills us with SIGBUS. `Auto` only maps files
+        // that haven't been written to lately to make that unlikely, and
+        // `--mmap` is documented as taking the risk.
+        Ok(unsafe { Mmap::open(&file) }.ok())
+    }
+
+    // Searches the whole of an input that's already in memory. Lines of
+    // plain UTF-8 with no NUL in them are searched in place. Anything that
+    // needs decoding or might be binary is read the usual way, which
+    // handles all of that, just without the file reads.
+    fn search_slice<M, S>(
+        &self,
+        matcher: &M,
+        bytes: &[u8],
+        path: &Path,
+        sink: &mut S,
+    ) -> io::Result<Stats>
+    where
+        M: Matcher + ?Sized,
+        S: Sink + ?Sized,
+    {
+        // As `encoding::decode` would do.
+        let text = bytes.strip_prefix(&UTF8_BOM).unwrap_or(bytes);
+        let text = match str::from_utf8(text) {
+            Ok(text) if !(self.detects_binary() && text.contains('\0')) => text,
+            _ => return self.search_reader(matcher, BufReader::new(bytes), path, sink),
+        };
+        // Every line that isn't a match is needed for `-v` or context, so
+        // skipping straight from match to match wouldn't save anything.
+        let scan = self.records == Records::Lines
+            && !self.invert_match
+            && self.before_context == 0
+            && self.after_context == 0
+            && matcher.matches_within_lines();
+        if !scan && !self.multiline {
+            return self.search_reader(matcher, BufReader::new(bytes), path, sink);
+        }
+        let mut run = Run::new(self, path, sink);
+        run.sink.begin(path)?;
+        run.stats.bytes_searched = text.len();
+        if self.multiline {
+            self.search_text(matcher, text, text.as_bytes(), &mut run)?;
+        } else {
+            self.search_scan(matcher, text, &mut run)?;
+        }
+        run.finish()
+    }
+
+    // Searches `reader` one line at a time, handing lines to the sink as it
+    // goes, so a multi-gigabyte log or a never-ending `tail -f` pipe can be
+    // searched without holding it in memory. Only the current line and the
+    // last `before_context` lines are kept around.
+    //
+    // With `with_records`, the "lines" are paragraphs or records instead,
+    // and a match can span the line breaks inside one. With
+    // `with_multiline`, the whole input is read first and searched in one
+    // go, so a match can span any number of lines.
+    //
+    // Lines are read as bytes, and any that aren't UTF-8 are decoded as
+    // Latin-1, so no file is an error just for its encoding. UTF-16 with a
+    // byte order mark is decoded too.
+    //
+    // A file with a NUL byte in it is binary, as far as grep is concerned.
+    // Unless `binary_files` says to treat it as text, the sink is told with
+    // `binary_data`, and no context lines are passed on from then on. With
+    // `BinaryFiles::WithoutMatch` the rest of the file isn't searched at
+    // all, and a file that's binary from the start isn't even begun. When
+    // NUL separates the records, as with `-z`, it's not a sign of anything.
+    //
+    // `path` is what the sink is told the lines come from.
+    pub fn search_reader<M, R, S>(
+        &self,
+        matcher: &M,
+        reader: R,
+        path: &Path,
+        sink: &mut S,
+    ) -> io::Result<Stats>
+    where
+        M: Matcher + ?Sized,
+        R: BufRead,
+        S: Sink + ?Sized,
+    {
+        let mut reader = encoding::decode(reader)?;
+        // Like grep, look for a NUL in the first buffer-full, and then in
+        // each line as it's read.
+        let nul = match self.detects_binary() {
+            true => reader.fill_buf()?.iter().position(|&b| b == 0),
+            false => None,
+        };
+        if nul.is_some() && self.binary_files == BinaryFiles::WithoutMatch {
+            return Ok(Stats::default());
+        }
+        let mut run = Run::new(self, path, sink);
+        run.sink.begin(path)?;
+        if let Some(offset) = nul {
+            run.binary = true;
+            run.stopped = !run.sink.binary_data(path, offset)?;
+        }
+        if self.multiline {
+            self.search_whole(matcher, reader, &mut run)?;
+        } else {
+            self.search_records(matcher, reader, &mut run)?;
+        }
+        run.finish()
+    }
+
+    fn detects_binary(&self) -> bool {
+        self.binary_files != BinaryFiles::Text
+            && !matches!(&self.records, Records::Separator(sep) if sep.contains(&0))
+    }
+
+    fn search_records<M, R, S>(&self, matcher: &M, reader: R, run: &mut Run<S>) -> io::Result<()>
+    where
+        M: Matcher + ?Sized,
+        R: BufRead,
+        S: Sink + ?Sized,
+    {
+        let mut reader = RecordReader::new(reader, self.records.clone());
+        while !run.stopped {
+            let (byte_offset, line_number) = match reader.next()? {
+                Some(start) => start,
+                None => break,
+            };
+            run.stats.bytes_searched = reader.consumed;
+            if !run.binary && self.detects_binary() {
+                if let Some(i) = reader.buf.iter().position(|&b| b == 0) {
+                    run.binary = true;
+                    // Too late to skip the file, but its lines needn't be
+                    // searched.
+                    if self.binary_files == BinaryFiles::WithoutMatch
+                        || !run.sink.binary_data(run.path, byte_offset + i)?
+                    {
+                        break;
+                    }
+                }
+            }
+            let line = decode_line(reader.record());
+            let line = line.as_ref();
+
+            let selected = matcher.is_match(line) != self.invert_match;
+            // An inverted line has nothing to highlight.
+            let spans = if selected && !self.invert_match {
+                matcher.find_all(line)
+            } else {
+                Vec::new()
+            };
+            run.stats.matches += spans.len();
+            let edit_distance = match selected {
+                true => matcher.edit_distance(line),
+                false => None,
+            };
+            let m = Match {
+                line_number,
+                byte_offset,
+                line,
+                raw: &reader.buf,
+                spans,
+                edit_distance,
+            };
+            run.line(selected, m)?;
+        }
+        Ok(())
+    }
+
+    // `with_multiline`: searches the whole input as one string, and then
+    // reports every line that a match touches, with the part of the match
+    // that's on it.
+    fn search_whole<M, R, S>(&self, matcher: &M, mut reader: R, run: &mut Run<S>) -> io::Result<()>
+    where
+        M: Matcher + ?Sized,
+        R: BufRead,
+        S: Sink + ?Sized,
+    {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        run.stats.bytes_searched = bytes.len();
+        if !run.binary && self.detects_binary() {
+            if let Some(i) = bytes.iter().position(|&b| b == 0) {
+                run.binary = true;
+                if self.binary_files == BinaryFiles::WithoutMatch
+                    || !run.sink.binary_data(run.path, i)?
+                {
+                    return Ok(());
+                }
+            }
+        }
+        let text = decode_line(&bytes);
+        self.search_text(matcher, text.as_ref(), &bytes, run)
+    }
+
+    // `raw` is what `text` was decoded from. Decoding never adds or removes
+    // a newline, so its lines are the same as those of `text`.
+    fn search_text<M, S>(
+        &self,
+        matcher: &M,
+        text: &str,
+        raw: &[u8],
+        run: &mut Run<S>,
+    ) -> io::Result<()>
+    where
+        M: Matcher + ?Sized,
+        S: Sink + ?Sized,
+    {
+        let matches = matcher.find_all(text);
+        if !self.invert_match {
+            run.stats.matches = matches.len();
+        }
+
+        // The first match that could touch the current line or a later one.
+        let mut first = 0;
+        let raw_lines = raw.split_inclusive(|&b| b == b'\n');
+        for (i, ((start, line), raw)) in crate::lines(text).zip(raw_lines).enumerate() {
+            if run.stopped {
+                break;
+            }
+            let end = start + line.len();
+            // Where the next line starts; a match that ends on this line's
+            // newline is still on this line.
+            let next = text[end..].find('\n').map_or(text.len(), |n| end + n + 1);
+            while first < matches.len() && matches[first].1 <= start && matches[first].0 < start {
+                first += 1;
+            }
+            let touching: Vec<(usize, usize)> = matches[first..]
+                .iter()
+                .take_while(|&&(s, _)| s < next)
+                .filter(|&&(s, e)| e > start || s >= start)
+                .copied()
+                .collect();
+            let selected = touching.is_empty() == self.invert_match;
+            let (spans, edit_distance) = if selected && !self.invert_match {
+                let spans = touching
+                    .iter()
+                    .map(|&(s, e)| {
+                        let e = e.clamp(start, end) - start;
+                        (s.clamp(start, end) - start, e)
+                    })
+                    .collect();
+                let (s, e) = touching[0];
+                (spans, matcher.edit_distance(&text[s..e]))
+            } else {
+                (Vec::new(), None)
+            };
+            let m = Match {
+                line_number: i + 1,
+                byte_offset: start,
+                line,
+                raw,
+                spans,
+                edit_distance,
+            };
+            run.line(selected, m)?;
+        }
+        Ok(())
+    }
+
+    // Searches `text` as one string for the lines with a match in them, as
+    // `search` does, and skips over the rest. Only for matchers whose
+    // matches stay within a line, and when no other lines are wanted.
+    fn search_scan<M, S>(&self, matcher: &M, text: &str, run: &mut Run<S>) -> io::Result<()>
+    where
+        M: Matcher + ?Sized,
+        S: Sink + ?Sized,
+    {
+        let mut line_number = 1;
+        // Newlines before this offset have been counted into `line_number`.
+        let mut counted = 0;
+        for (byte_offset, line) in crate::scan(matcher, text) {
+            line_number += text.as_bytes()[counted..byte_offset]
+                .iter()
+                .filter(|&&b| b == b'\n')
+                .count();
+            counted = byte_offset;
+            let spans = matcher.find_all(line);
+            run.stats.matches += spans.len();
+            let m = Match {
+                line_number,
+                byte_offset,
+                line,
+                raw: crate::line_bytes(text, byte_offset),
+                spans,
+                edit_distance: matcher.edit_distance(line),
+            };
+            run.line(true, m)?;
+            if run.stopped {
+                // Only count what was searched, as reading it would.
+                let end = text[byte_offset..].find('\n');
+                run.stats.bytes_searched = end.map_or(text.len(), |i| byte_offset + i + 1);
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The state of one search: the context lines that might still be needed,
+// and the counts so far.
+struct Run<'a, S: ?Sized> {
+    path: &'a Path,
+    sink: &'a mut S,
+    before_context: usize,
+    after_context: usize,
+    // Line number, byte offset, text and raw bytes of each line.
+    before: VecDeque<(usize, usize, String, Vec<u8>)>,
+    after_remaining: usize,
+    last_printed: Option<usize>,
+    // Whether the input has turned out to be binary.
+    binary: bool,
+    // Whether the sink has asked to stop.
+    stopped: bool,
+    stats: Stats,
+}
+
+impl<'a, S: Sink + ?Sized> Run<'a, S> {
+    fn new(searcher: &Searcher, path: &'a Path, sink: &'a mut S) -> Run<'a, S> {
+        Run {
+            path,
+            sink,
+            before_context: searcher.before_context,
+            after_context: searcher.after_context,
+            before: VecDeque::with_capacity(searcher.before_context),
+            after_remaining: 0,
+            last_printed: None,
+            binary: false,
+            stopped: false,
+            stats: Stats {
+                searches: 1,
+                ..Stats::default()
+            },
+        }
+    }
+
+    // Hands one line to the sink, as a selected line or context, or keeps
+    // it in case it's needed as context later.
+    fn line(&mut self, selected: bool, m: Match) -> io::Result<()> {
+        let context = self.before_context > 0 || self.after_context > 0;
+        if selected {
+            self.stats.matched_lines += 1;
+            if context && !self.binary {
+                let group_start = self.before.front().map_or(m.line_number, |&(n, ..)| n);
+                let adjacent = self
+                    .last_printed
+                    .is_some_and(|last| group_start <= last + 1);
+                if !adjacent {
+                    self.sink.context_break()?;
+                }
+                for (n, offset, line, raw) in self.before.drain(..) {
+                    if !self
+                        .sink
+                        .context(self.path, &context_match(n, offset, &line, &raw))?
+                    {
+                        self.stopped = true;
+                    }
+                }
+                if self.stopped {
+                    return Ok(());
+                }
+            }
+            self.stopped = !self.sink.matched(self.path, &m)?;
+            self.after_remaining = self.after_context;
+            self.last_printed = Some(m.line_number);
+        } else if self.after_remaining > 0 && !self.binary {
+            let m = context_match(m.line_number, m.byte_offset, m.line, m.raw);
+            self.stopped = !self.sink.context(self.path, &m)?;
+            self.after_remaining -= 1;
+            self.last_printed = Some(m.line_number);
+        } else if self.before_context > 0 && !self.binary {
+            if self.before.len() == self.before_context {
+                self.before.pop_front();
+            }
+            self.before.push_back((
+                m.line_number,
+                m.byte_offset,
+                m.line.to_string(),
+                m.raw.to_vec(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<Stats> {
+        if self.stats.matched_lines > 0 {
+            self.stats.searches_with_match = 1;
+        }
+        self.sink.end(self.path, &self.stats)?;
+        Ok(self.stats)
+    }
+}
+
+// Splits its input into records, keeping track of where each one starts.
+struct RecordReader<R> {
+    reader: R,
+    records: Records,
+    // The record just read, with whatever ended it.
+    buf: Vec<u8>,
+    // Bytes read so far, and how many of them were newlines.
+    consumed: usize,
+    newlines: usize,
+}
+
+impl<R: BufRead> RecordReader<R> {
+    fn new(reader: R, records: Records) -> RecordReader<R> {
+        RecordReader {
+            reader,
+            records,
+            buf: Vec::new(),
+            consumed: 0,
+            newlines: 0,
+        }
+    }
+
+    // Reads the next record into `buf`, returning its byte offset and the
+    // number of the line it starts on, or `None` at the end of the input.
+    fn next(&mut self) -> io::Result<Option<(usize, usize)>> {
+        self.buf.clear();
+        match &self.records {
+            Records::Lines => self.read_until(b"\n"),
+            Records::Separator(sep) => {
+                let sep = sep.clone();
+                self.read_until(&sep)
+            }
+            Records::Paragraphs => {
+                let mut start = None;
+                let mut line = Vec::new();
+                loop {
+                    line.clear();
+                    let read = self.reader.read_until(b'\n', &mut line)?;
+                    if read == 0 {
+                        break;
+                    }
+                    let at = (self.consumed, self.newlines + 1);
+                    self.consumed += read;
+                    self.newlines += line.ends_with(b"\n") as usize;
+                    // A blank line ends a paragraph; any more of them
+                    // before the next one are skipped.
+                    if line.iter().all(u8::is_ascii_whitespace) {
+                        if start.is_some() {
+                            break;
+                        }
+                        continue;
+                    }
+                    start.get_or_insert(at);
+                    self.buf.extend_from_slice(&line);
+                }
+                Ok(start)
+            }
+        }
+    }
+
+    fn read_until(&mut self, sep: &[u8]) -> io::Result<Option<(usize, usize)>> {
+        let start = (self.consumed, self.newlines + 1);
+        let last = match sep.last() {
+            Some(&last) => last,
+            None => b'\n',
+        };
+        loop {
+            if self.reader.read_until(last, &mut self.buf)? == 0 || self.buf.ends_with(sep) {
+                break;
+            }
+        }
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        self.consumed += self.buf.len();
+        self.newlines += self.buf.iter().filter(|&&b| b == b'\n').count();
+        Ok(Some(start))
+    }
+
+    // The record in `buf`, without what ended it.
+    fn record(&self) -> &[u8] {
+        match &self.records {
+            Records::Lines | Records::Paragraphs => trim_newline(&self.buf),
+            Records::Separator(sep) => self.buf.strip_suffix(&sep[..]).unwrap_or(&self.buf),
+        }
+    }
+}
+
+fn context_match<'a>(
+    line_number: usize,
+    byte_offset: usize,
+    line: &'a str,
+    raw: &'a [u8],
+) -> Match<'a> {
+    Match {
+        line_number,
+        byte_offset,
+        line,
+        raw,
+        spans: Vec::new(),
+        edit_distance: None,
+    }
+}
+
+fn trim_newline(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, FnSink, MmapChoice, OutputMode, Printer, Records};
+
+    // Searches with `config`, printing like `run` does.
+    fn search(config: &Config, contents: &str) -> String {
+        let searcher = crate::new_searcher(config);
+        let mut printer = Printer::new(Vec::new(), config.line_number)
+            .with_output(config.output)
+            .with_replace(config.replace.clone())
+            .with_terminator(config.records.terminator());
+        searcher
+            .search_reader(
+                &config.queries[0],
+                contents.as_bytes(),
+                Path::new(STDIN_LABEL),
+                &mut printer,
+            )
+            .unwrap();
+        String::from_utf8(printer.into_inner()).unwrap()
+    }
+
+    fn context(before: usize, after: usize) -> Config {
+        Config {
+            queries: vec!["x".to_string()],
+            line_number: true,
+            before_context: before,
+            after_context: after,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn lines_without_context() {
+        assert_eq!("2:b x\n4:d x\n", search(&context(0, 0), "a\nb x\nc\nd x"));
+    }
+
+    #[test]
+    fn context_groups() {
+        let contents = "1\n2 x\n3\n4\n5\n6\n7 x\n8\n";
+        assert_eq!(
+            "1-1\n2:2 x\n3-3\n--\n6-6\n7:7 x\n8-8\n",
+            search(&context(1, 1), contents)
+        );
+        // Overlapping context is merged into one group.
+        assert_eq!(
+            "1-1\n2:2 x\n3-3\n4-4\n5-5\n6-6\n7:7 x\n8-8\n",
+            search(&context(2, 2), contents)
+        );
+        assert_eq!(
+            "2:2 x\n3-3\n--\n7:7 x\n8-8\n",
+            search(&context(0, 1), contents)
+        );
+    }
+
+    #[test]
+    fn count_and_invert() {
+        let config = Config {
+            output: OutputMode::Count,
+            invert_match: true,
+            ..context(0, 0)
+        };
+        assert_eq!("2\n", search(&config, "a\nb x\r\nc\n"));
+    }
+
+    #[test]
+    fn only_matching() {
+        let config = Config {
+            output: OutputMode::OnlyMatching,
+            ..context(1, 1)
+        };
+        assert_eq!("2:x\n2:x\n4:x\n", search(&config, "a\nx b x\nc\nx"));
+    }
+
+    #[test]
+    fn files_with_and_without_match() {
+        let with = Config {
+            output: OutputMode::FilesWithMatches,
+            ..context(0, 0)
+        };
+        let without = Config {
+            output: OutputMode::FilesWithoutMatch,
+            ..context(0, 0)
+        };
+        assert_eq!("(standard input)\n", search(&with, "a\nx"));
+        assert_eq!("", search(&with, "a\nb"));
+        assert_eq!("", search(&without, "a\nx"));
+        assert_eq!("(standard input)\n", search(&without, "a\nb"));
+    }
+
+    #[test]
+    fn binary_files() {
+        let contents = "a\nx\0\nx\n";
+        assert_eq!(
+            "Binary file (standard input) matches\n",
+            search(&context(0, 0), contents)
+        );
+        let text = Config {
+            binary_files: BinaryFiles::Text,
+            ..context(0, 0)
+        };
+        assert_eq!("2:x\0\n3:x\n", search(&text, contents));
+        let skip = Config {
+            binary_files: BinaryFiles::WithoutMatch,
+            ..context(0, 0)
+        };
+        assert_eq!("", search(&skip, contents));
+        // Counting doesn't print the lines, so it counts them all.
+        let count = Config {
+            output: OutputMode::Count,
+            ..context(0, 0)
+        };
+        assert_eq!("2\n", search(&count, contents));
+    }
+
+    #[test]
+    fn latin1() {
+        let config = Config {
+            queries: vec!["caf\u{e9}".to_string()],
+            ..Config::default()
+        };
+        let contents = b"cafe\ncaf\xe9\n";
+        let mut printer = Printer::new(Vec::new(), false);
+        Searcher::new()
+            .search_reader(
+                &config.queries[0],
+                &contents[..],
+                Path::new("f"),
+                &mut printer,
+            )
+            .unwrap();
+        assert_eq!(
+            "caf\u{e9}\n",
+            String::from_utf8(printer.into_inner()).unwrap()
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let mut printer = Printer::new(Vec::new(), false);
+        let stats = Searcher::new()
+            .search_reader(
+                "x",
+                "a\nx b x\nc\n".as_bytes(),
+                Path::new("f"),
+                &mut printer,
+            )
+            .unwrap();
+        assert_eq!(
+            Stats {
+                searches: 1,
+                searches_with_match: 1,
+                bytes_searched: 10,
+                matched_lines: 1,
+                matches: 2,
+            },
+            stats
+        );
+    }
+
+    #[test]
+    fn replace() {
+        let config = Config {
+            replace: Some("yy".to_string()),
+            ..context(0, 1)
+        };
+        let contents = "a\nb x x\nc x\n";
+        assert_eq!("2:b yy yy\n3:c yy\n", search(&config, contents));
+        let config = Config {
+            output: OutputMode::OnlyMatching,
+            ..config
+        };
+        assert_eq!("2:yy\n2:yy\n3:yy\n", search(&config, contents));
+    }
+
+    #[test]
+    fn sink_callbacks() {
+        // Records each callback, and stops after the second match.
+        struct Recorder(Vec<String>);
+        impl Sink for Recorder {
+            fn begin(&mut self, _: &Path) -> io::Result<()> {
+                self.0.push("begin".to_string());
+                Ok(())
+            }
+            fn matched(&mut self, _: &Path, m: &Match) -> io::Result<bool> {
+                self.0
+                    .push(format!("match {} at {}", m.line_number, m.byte_offset));
+                Ok(m.line_number < 4)
+            }
+            fn context(&mut self, _: &Path, m: &Match) -> io::Result<bool> {
+                self.0.push(format!("context {}", m.line_number));
+                Ok(true)
+            }
+            fn context_break(&mut self) -> io::Result<()> {
+                self.0.push("break".to_string());
+                Ok(())
+            }
+            fn end(&mut self, _: &Path, stats: &Stats) -> io::Result<()> {
+                self.0.push(format!("end {}", stats.matched_lines));
+                Ok(())
+            }
+        }
+        let mut recorder = Recorder(Vec::new());
+        Searcher::new()
+            .with_context(0, 1)
+            .search_reader(
+                "x",
+                "x\na\nb\nx\nc\nx\n".as_bytes(),
+                Path::new("f"),
+                &mut recorder,
+            )
+            .unwrap();
+        assert_eq!(
+            vec![
+                "begin",
+                "break",
+                "match 1 at 0",
+                "context 2",
+                "break",
+                "match 4 at 6",
+                "end 2"
+            ],
+            recorder.0
+        );
+
+        let mut lines = Vec::new();
+        Searcher::new()
+            .with_invert_match(true)
+            .search_reader(
+                "x",
+                "a\nx\nb\n".as_bytes(),
+                Path::new("f"),
+                &mut FnSink(|_: &Path, m: &Match| {
+                    lines.push(m.line.to_string());
+                    Ok(true)
+                }),
+            )
+            .unwrap();
+        assert_eq!(vec!["a", "b"], lines);
+    }
+
+    #[test]
+    fn records() {
+        let contents = "a\nb x\n\n\nc\nd\n \ne\nx f\n";
+        let paragraphs = Config {
+            records: Records::Paragraphs,
+            ..context(0, 0)
+        };
+        assert_eq!("1:a\nb x\n\n8:e\nx f\n\n", search(&paragraphs, contents));
+        // A match can span lines within a record.
+        let config = Config {
+            queries: vec!["c\nd".to_string()],
+            ..paragraphs
+        };
+        assert_eq!("5:c\nd\n\n", search(&config, contents));
+
+        let nul = Config {
+            records: Records::Separator(vec![0]),
+            ..context(0, 0)
+        };
+        // NUL separates the records, so it doesn't make the input binary.
+        assert_eq!("1:x\n1\x002:x\x00", search(&nul, "x\n1\x00y\x00x\x00"));
+        let custom = Config {
+            records: Records::Separator(b"--\n".to_vec()),
+            ..context(0, 0)
+        };
+        assert_eq!("1:a x\n--\n3:b x--\n", search(&custom, "a x\n--\nb x"));
+    }
+
+    #[test]
+    fn multiline() {
+        let config = Config {
+            queries: vec!["b\nc".to_string()],
+            multiline: true,
+            ..context(0, 1)
+        };
+        let contents = "a b\nc d\ne\nf\n";
+        assert_eq!("1:a b\n2:c d\n3-e\n", search(&config, contents));
+        let config = Config {
+            output: OutputMode::OnlyMatching,
+            ..config
+        };
+        assert_eq!("1:b\n2:c\n", search(&config, contents));
+        let config = Config {
+            invert_match: true,
+            output: OutputMode::Count,
+            ..config
+        };
+        assert_eq!("2\n", search(&config, contents));
+    }
+
+    #[test]
+    fn mmap() {
+        let path = std::env::temp_dir().join(format!("minigrep-mmap-{}.txt", std::process::id()));
+        // Searches the file with and without a map, which have to agree.
+        let search = |config: &Config, contents: &[u8]| {
+            std::fs::write(&path, contents).unwrap();
+            let results: Vec<(String, Stats)> = [MmapChoice::Always, MmapChoice::Never]
+                .iter()
+                .map(|&mmap| {
+                    let mut printer = Printer::new(Vec::new(), true).with_output(config.output);
+                    let stats = crate::new_searcher(config)
+                        .with_mmap(mmap)
+                        .search_path(&config.queries[0], &path, &mut printer)
+                        .unwrap();
+                    (String::from_utf8(printer.into_inner()).unwrap(), stats)
+                })
+                .collect();
+            assert_eq!(results[0], results[1]);
+            results[0].0.clone()
+        };
+
+        let contents = b"\xef\xbb\xbfa x\r\nb\nc x x\nd";
+        assert_eq!("1:a x\n3:c x x\n", search(&context(0, 0), contents));
+        assert_eq!(
+            "1:a x\n2-b\n3:c x x\n4-d\n",
+            search(&context(0, 1), contents)
+        );
+        let count = Config {
+            output: OutputMode::Count,
+            ..context(0, 0)
+        };
+        assert_eq!("2\n", search(&count, contents));
+        // Stopping early counts the same bytes as searched.
+        let list = Config {
+            output: OutputMode::FilesWithMatches,
+            ..context(0, 0)
+        };
+        search(&list, contents);
+        let multiline = Config {
+            queries: vec!["x\nc".to_string()],
+            multiline: true,
+            ..context(0, 0)
+        };
+        assert_eq!("2:b x\n3:c x x\n", search(&multiline, b"a\nb x\nc x x\n"));
+        // Latin-1 and binary files aren't searched in place.
+        assert_eq!("2:caf\u{e9} x\n", search(&context(0, 0), b"a\ncaf\xe9 x\n"));
+        assert_eq!(
+            format!("Binary file {} matches\n", path.display()),
+            search(&context(0, 0), b"a x\n\0\n")
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
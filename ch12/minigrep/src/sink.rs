@@ -0,0 +1,64 @@
+use std::io;
+use std::path::Path;
+
+use crate::searcher::Stats;
+use crate::Match;
+
+// Where a `Searcher` sends what it finds. The searcher decides which lines
+// are selected and which are context; a sink decides what to do with them,
+// whether that's printing them like grep, writing JSON, or collecting them
+// into a `Vec`.
+//
+// The callbacks that return `Ok(false)` stop the search of the current
+// input early, e.g. once `-l` knows a file matches. An error stops it too,
+// and is passed back to whoever called the searcher.
+pub trait Sink {
+    // Called before anything else for an input.
+    fn begin(&mut self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    // A selected line: one that matched or, with `-v`, one that didn't.
+    fn matched(&mut self, path: &Path, m: &Match) -> io::Result<bool>;
+
+    // A line printed around a selected one for `-A`, `-B` and `-C`.
+    fn context(&mut self, _path: &Path, _m: &Match) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    // Called before each group of selected and context lines that doesn't
+    // run straight on from the last one, but only when there's context.
+    fn context_break(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // The input has a NUL byte at `byte_offset`, so it's probably binary.
+    // Everything passed on after this is from a binary input.
+    fn binary_data(&mut self, _path: &Path, _byte_offset: usize) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    // Called last, with the counts for the input, unless there was an error.
+    fn end(&mut self, _path: &Path, _stats: &Stats) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// A sink made from a closure that's given each selected line, for when
+// that's all that's needed:
+//
+//   let mut lines = Vec::new();
+//   searcher.search_reader(&matcher, reader, path, &mut FnSink(|_, m| {
+//       lines.push(m.line.to_string());
+//       Ok(true)
+//   }))?;
+pub struct FnSink<F>(pub F);
+
+impl<F> Sink for FnSink<F>
+where
+    F: FnMut(&Path, &Match) -> io::Result<bool>,
+{
+    fn matched(&mut self, path: &Path, m: &Match) -> io::Result<bool> {
+        (self.0)(path, m)
+    }
+}
@@ -0,0 +1,355 @@
+use std::error::Error;
+use std::fmt;
+
+// Just enough TOML for a config file: `[table]` headers, `key = value`
+// lines and `#` comments, where a value is a string ("basic" or 'literal'),
+// an integer, a boolean or an array of those. Dotted keys, inline tables,
+// dates and multi-line strings aren't supported.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    // What the value is, for error messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Boolean(_) => "a boolean",
+            Value::Array(_) => "an array",
+        }
+    }
+}
+
+// One `key = value` line, with the table it's in ("" before any header)
+// and its line number, to point at in errors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub table: String,
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+// The entries of a document, in order. Defining a key twice in the same
+// table is an error, as in real TOML.
+pub fn parse(text: &str) -> Result<Vec<Entry>, ParseError> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut table = String::new();
+    loop {
+        parser.skip_blank_lines();
+        let line = parser.line;
+        match parser.peek() {
+            None => return Ok(entries),
+            Some('[') => {
+                parser.pos += 1;
+                parser.skip_spaces();
+                table = parser.key()?;
+                parser.skip_spaces();
+                parser.expect(']')?;
+            }
+            Some(_) => {
+                let key = parser.key()?;
+                parser.skip_spaces();
+                parser.expect('=')?;
+                parser.skip_spaces();
+                let value = parser.value()?;
+                if entries.iter().any(|e| e.table == table && e.key == key) {
+                    return Err(parser.error(format!("'{}' is defined twice", key)));
+                }
+                entries.push(Entry {
+                    table: table.clone(),
+                    key,
+                    value,
+                    line,
+                });
+            }
+        }
+        parser.end_of_line()?;
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            line: self.line,
+            message,
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.next();
+                Ok(())
+            }
+            Some(c) => Err(self.error(format!("expected '{}', found '{}'", expected, c))),
+            None => Err(self.error(format!("expected '{}' at the end of the file", expected))),
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while let Some(' ') | Some('\t') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.pos += 1;
+            }
+        }
+    }
+
+    // Spaces, comments and newlines: everything allowed between entries,
+    // and between the items of an array.
+    fn skip_blank_lines(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+            match self.peek() {
+                Some('\n') => {
+                    self.next();
+                }
+                Some('\r') if self.chars.get(self.pos + 1) == Some(&'\n') => {
+                    self.pos += 1;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), ParseError> {
+        self.skip_spaces();
+        self.skip_comment();
+        if self.peek() == Some('\r') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.next();
+                Ok(())
+            }
+            Some(c) => Err(self.error(format!("unexpected '{}' after a value", c))),
+        }
+    }
+
+    fn key(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let start = self.pos;
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                {
+                    self.pos += 1;
+                }
+                if self.pos == start {
+                    return Err(self.error("expected a key".to_string()));
+                }
+                Ok(self.chars[start..self.pos].iter().collect())
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => {
+                self.next();
+                let mut items = Vec::new();
+                loop {
+                    self.skip_blank_lines();
+                    if self.peek() == Some(']') {
+                        self.next();
+                        return Ok(Value::Array(items));
+                    }
+                    items.push(self.value()?);
+                    self.skip_blank_lines();
+                    // A trailing comma is allowed.
+                    match self.peek() {
+                        Some(',') => {
+                            self.next();
+                        }
+                        Some(']') => {}
+                        _ => return Err(self.error("expected ',' or ']' in an array".to_string())),
+                    }
+                }
+            }
+            _ => {
+                let start = self.pos;
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '+')
+                {
+                    self.pos += 1;
+                }
+                let word: String = self.chars[start..self.pos].iter().collect();
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => word
+                        .replace('_', "")
+                        .parse()
+                        .map(Value::Integer)
+                        .map_err(|_| self.error(format!("invalid value '{}'", word))),
+                }
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, ParseError> {
+        self.next();
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('u') => self.unicode_escape(4)?,
+                        Some('U') => self.unicode_escape(8)?,
+                        _ => return Err(self.error("invalid escape in a string".to_string())),
+                    };
+                    s.push(c);
+                }
+                Some('\n') | None => return Err(self.error("unterminated string".to_string())),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self, digits: usize) -> Result<char, ParseError> {
+        let end = (self.pos + digits).min(self.chars.len());
+        let hex: String = self.chars[self.pos..end].iter().collect();
+        self.pos = end;
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .filter(|_| hex.len() == digits)
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error(format!("invalid unicode escape '{}'", hex)))
+    }
+
+    // `'...'`: no escapes at all, which suits globs and regexes.
+    fn literal_string(&mut self) -> Result<String, ParseError> {
+        self.next();
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('\'') => return Ok(s),
+                Some('\n') | None => return Err(self.error("unterminated string".to_string())),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries() {
+        let text = "\
+# defaults
+flags = [\"-n\", '--color=always',]  # trailing comma
+threads = 4
+
+[types]
+rust = [
+    '*.rs',   # sources
+    \"build.rs\",
+]
+\"web app\" = \"\\u00e9\\t\"
+strict = false
+";
+        let entries = parse(text).unwrap();
+        let summary: Vec<(&str, &str, usize)> = entries
+            .iter()
+            .map(|e| (e.table.as_str(), e.key.as_str(), e.line))
+            .collect();
+        assert_eq!(
+            vec![
+                ("", "flags", 2),
+                ("", "threads", 3),
+                ("types", "rust", 6),
+                ("types", "web app", 10),
+                ("types", "strict", 11),
+            ],
+            summary
+        );
+        let string = |s: &str| Value::String(s.to_string());
+        assert_eq!(
+            Value::Array(vec![string("-n"), string("--color=always")]),
+            entries[0].value
+        );
+        assert_eq!(Value::Integer(4), entries[1].value);
+        assert_eq!(
+            Value::Array(vec![string("*.rs"), string("build.rs")]),
+            entries[2].value
+        );
+        assert_eq!(string("é\t"), entries[3].value);
+        assert_eq!(Value::Boolean(false), entries[4].value);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |text: &str| parse(text).unwrap_err().to_string();
+        assert_eq!("line 2: expected '=', found ':'", error("a = 1\nb: 2"));
+        assert_eq!("line 1: unterminated string", error("a = \"x"));
+        assert_eq!("line 3: 'a' is defined twice", error("a = 1\n\na = 2"));
+        assert_eq!("line 1: unexpected '2' after a value", error("a = 1 2"));
+        assert_eq!("line 1: invalid value 'yes'", error("a = yes"));
+    }
+}
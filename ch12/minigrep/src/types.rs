@@ -0,0 +1,131 @@
+use std::path::Path;
+
+use crate::glob::Glob;
+
+// File types for `-t` and `-T`, each a name and the globs its files' names
+// match. The config file's `[types]` can add to these or redefine them.
+const BUILT_IN: &[(&str, &[&str])] = &[
+    ("c", &["*.c", "*.h"]),
+    (
+        "cpp",
+        &["*.cpp", "*.cc", "*.cxx", "*.hpp", "*.hh", "*.hxx", "*.h"],
+    ),
+    ("css", &["*.css", "*.scss", "*.sass", "*.less"]),
+    ("go", &["*.go"]),
+    ("html", &["*.html", "*.htm"]),
+    ("java", &["*.java"]),
+    ("js", &["*.js", "*.mjs", "*.cjs", "*.jsx"]),
+    ("json", &["*.json"]),
+    ("make", &["Makefile", "makefile", "GNUmakefile", "*.mk"]),
+    ("markdown", &["*.md", "*.markdown", "*.mdown", "*.mkd"]),
+    ("py", &["*.py", "*.pyi"]),
+    ("rust", &["*.rs"]),
+    ("sh", &["*.sh", "*.bash", "*.zsh"]),
+    ("toml", &["*.toml", "Cargo.lock"]),
+    ("ts", &["*.ts", "*.tsx", "*.mts", "*.cts"]),
+    ("txt", &["*.txt"]),
+    ("yaml", &["*.yaml", "*.yml"]),
+];
+
+pub fn built_in() -> Vec<(String, Vec<String>)> {
+    BUILT_IN
+        .iter()
+        .map(|&(name, globs)| {
+            let globs = globs.iter().map(|&glob| glob.to_string()).collect();
+            (name.to_string(), globs)
+        })
+        .collect()
+}
+
+// Adds `more` to `types`, replacing any that have the same name.
+pub fn define(types: &mut Vec<(String, Vec<String>)>, more: Vec<(String, Vec<String>)>) {
+    for (name, globs) in more {
+        match types.iter_mut().find(|(defined, _)| *defined == name) {
+            Some(existing) => existing.1 = globs,
+            None => types.push((name, globs)),
+        }
+    }
+}
+
+// Which files `-t` and `-T` let through.
+#[derive(Debug, Clone, Default)]
+pub struct TypeFilter {
+    // The globs of every type picked with `-t`; a file has to match one.
+    select: Vec<Glob>,
+    // The globs of the types turned away with `-T`, which take precedence.
+    negate: Vec<Glob>,
+}
+
+impl TypeFilter {
+    // `select` and `negate` name types defined in `types`; any that aren't
+    // are ignored, since the config has already checked them.
+    pub fn new(
+        types: &[(String, Vec<String>)],
+        select: &[String],
+        negate: &[String],
+    ) -> TypeFilter {
+        let globs = |names: &[String]| -> Vec<Glob> {
+            types
+                .iter()
+                .filter(|(name, _)| names.contains(name))
+                .flat_map(|(_, globs)| globs.iter().map(|glob| Glob::new(glob)))
+                .collect()
+        };
+        TypeFilter {
+            select: globs(select),
+            negate: globs(negate),
+        }
+    }
+
+    // Only the file's name counts, not the directories it's in.
+    pub fn allows(&self, file: &Path) -> bool {
+        if self.select.is_empty() && self.negate.is_empty() {
+            return true;
+        }
+        let name: Vec<char> = match file.file_name() {
+            Some(name) => name.to_string_lossy().chars().collect(),
+            None => return false,
+        };
+        let matches = |globs: &[Glob]| globs.iter().any(|glob| glob.is_match_chars(&name));
+        (self.select.is_empty() || matches(&self.select)) && !matches(&self.negate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters() {
+        let mut types = built_in();
+        define(
+            &mut types,
+            vec![
+                (
+                    "rust".to_string(),
+                    vec!["*.rs".to_string(), "*.rs.in".to_string()],
+                ),
+                (
+                    "docs".to_string(),
+                    vec!["*.md".to_string(), "*.txt".to_string()],
+                ),
+            ],
+        );
+        let names =
+            |names: &[&str]| -> Vec<String> { names.iter().map(|&n| n.to_string()).collect() };
+
+        let filter = TypeFilter::new(&types, &names(&["rust"]), &[]);
+        assert!(filter.allows(Path::new("src/lib.rs")));
+        assert!(filter.allows(Path::new("build.rs.in")));
+        assert!(!filter.allows(Path::new("README.md")));
+
+        let filter = TypeFilter::new(&types, &[], &names(&["markdown"]));
+        assert!(filter.allows(Path::new("src/lib.rs")));
+        assert!(!filter.allows(Path::new("docs/guide.md")));
+
+        let filter = TypeFilter::new(&types, &names(&["docs"]), &names(&["markdown"]));
+        assert!(filter.allows(Path::new("notes.txt")));
+        assert!(!filter.allows(Path::new("README.md")));
+        assert!(TypeFilter::default().allows(Path::new("anything")));
+    }
+}
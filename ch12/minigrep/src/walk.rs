@@ -0,0 +1,202 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::glob::GlobFilter;
+use crate::ignore::{self, Ignore};
+use crate::index;
+use crate::Warning;
+
+// Recursively lists every regular file under `root`, the way `grep -r`
+// would, minus anything excluded by `.gitignore`/`.ignore` files along the
+// way. Entries are sorted by name so the output order is stable. Symbolic
+// links are not followed.
+//
+// `ignore` holds extra gitignore-style globs that apply below `root`, with
+// less say than any ignore file found there. `globs` has more say than any
+// of them; see `GlobFilter`.
+//
+// A directory or ignore file that can't be read is passed to `warn`, and
+// the walk carries on without it, like `grep -r` does.
+pub fn walk(
+    root: &Path,
+    ignore: &[String],
+    globs: &GlobFilter,
+    warn: &mut dyn FnMut(Warning),
+) -> Vec<PathBuf> {
+    let mut walker = Walker {
+        root,
+        globs,
+        ignores: Vec::new(),
+        files: Vec::new(),
+        warn,
+    };
+    if !ignore.is_empty() {
+        walker.ignores.push(Ignore::parse(root, &ignore.join("\n")));
+    }
+    walker.walk_dir(root);
+    walker.files
+}
+
+struct Walker<'a> {
+    root: &'a Path,
+    globs: &'a GlobFilter,
+    ignores: Vec<Ignore>,
+    files: Vec<PathBuf>,
+    warn: &'a mut dyn FnMut(Warning),
+}
+
+impl Walker<'_> {
+    // Whether to search `path`, or for a directory, look inside it.
+    fn wanted(&self, path: &Path, is_dir: bool) -> bool {
+        if !self.globs.is_empty() {
+            let relative = path
+                .strip_prefix(self.root)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .replace(std::path::MAIN_SEPARATOR, "/");
+            match self.globs.matched(&relative) {
+                Some(include) => return include,
+                // An including glob like `*.rs` leaves out other files, but
+                // not the directories they might be in.
+                None if !is_dir && !self.globs.includes_unmatched() => return false,
+                None => {}
+            }
+        }
+        !ignore::is_ignored(&self.ignores, path, is_dir)
+    }
+
+    fn walk_dir(&mut self, dir: &Path) {
+        let pushed = match Ignore::load(dir, self.warn) {
+            Some(ignore) => {
+                self.ignores.push(ignore);
+                true
+            }
+            None => false,
+        };
+
+        let entries = fs::read_dir(dir).and_then(|entries| entries.collect::<io::Result<Vec<_>>>());
+        let mut entries = match entries {
+            Ok(entries) => entries,
+            Err(error) => {
+                (self.warn)(Warning {
+                    path: dir.to_path_buf(),
+                    error,
+                });
+                Vec::new()
+            }
+        };
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let path = entry.path();
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(error) => {
+                    (self.warn)(Warning { path, error });
+                    continue;
+                }
+            };
+            if file_type.is_dir() {
+                // Git's own bookkeeping is never worth searching.
+                if entry.file_name() == ".git" || !self.wanted(&path, true) {
+                    continue;
+                }
+                self.walk_dir(&path);
+            } else if file_type.is_file()
+                // Nor is minigrep's own.
+                && entry.file_name() != index::FILE_NAME
+                && self.wanted(&path, false)
+            {
+                self.files.push(path);
+            }
+        }
+
+        if pushed {
+            self.ignores.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn respects_ignore_files() {
+        let root = env::temp_dir().join(format!("minigrep-walk-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(root.join("src/.ignore"), "generated.rs\n").unwrap();
+        fs::write(root.join("src/lib.rs"), "").unwrap();
+        fs::write(root.join("src/generated.rs"), "").unwrap();
+        fs::write(root.join("target/debug/out"), "").unwrap();
+        fs::write(root.join(".git/HEAD"), "").unwrap();
+        fs::write(root.join("build.log"), "").unwrap();
+        fs::write(root.join("README"), "").unwrap();
+
+        let files = walk(
+            &root,
+            &["README".to_string()],
+            &GlobFilter::default(),
+            &mut |warning| panic!("{}", warning),
+        );
+        let names: Vec<_> = files
+            .iter()
+            .map(|f| f.strip_prefix(&root).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            vec![
+                PathBuf::from(".gitignore"),
+                PathBuf::from("src/.ignore"),
+                PathBuf::from("src/lib.rs"),
+            ],
+            names
+        );
+
+        // Globs override the ignore files: `build.log` is in `.gitignore`.
+        let globs = GlobFilter::new(&["*.log", "*.rs", "!src/**"]);
+        let files = walk(&root, &[], &globs, &mut |warning| panic!("{}", warning));
+        assert_eq!(vec![root.join("build.log")], files);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn carries_on_past_unreadable_entries() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = env::temp_dir().join(format!("minigrep-walk-locked-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("locked")).unwrap();
+        fs::create_dir_all(root.join("open/.gitignore")).unwrap();
+        fs::write(root.join("locked/secret"), "").unwrap();
+        fs::write(root.join("open/file"), "").unwrap();
+        fs::write(root.join("z"), "").unwrap();
+        fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o000)).unwrap();
+        // Permissions don't stop root.
+        let enforced = fs::read_dir(root.join("locked")).is_err();
+
+        let mut warnings = Vec::new();
+        let files = walk(&root, &[], &GlobFilter::default(), &mut |warning| {
+            warnings.push(warning.path)
+        });
+        fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(files.contains(&root.join("open/file")));
+        assert!(files.contains(&root.join("z")));
+        // An ignore file that's really a directory can't be read either.
+        let mut expected = vec![root.join("open/.gitignore")];
+        if enforced {
+            expected.insert(0, root.join("locked"));
+            assert!(!files.contains(&root.join("locked/secret")));
+        }
+        assert_eq!(expected, warnings);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
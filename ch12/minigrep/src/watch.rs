@@ -0,0 +1,594 @@
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::searcher::Stats;
+use crate::{Match, Matcher, Searcher, Sink, Warning};
+
+// How often to look at the files when nothing tells us they've changed.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// With inotify, how long to wait for an event before looking anyway, for
+// changes it doesn't report: a file in a directory that didn't exist yet,
+// or one on a network filesystem.
+#[cfg(target_os = "linux")]
+const RESCAN_INTERVAL: Duration = Duration::from_secs(5);
+// How much of the start of a file is kept, to tell when it's been truncated
+// and written again past where we'd got to.
+const FINGERPRINT_LEN: usize = 64;
+
+// `--watch`: follows files like `tail -F`, searching whatever is written to
+// them. Each file is searched from the start the first time round, and
+// after that only the lines added since.
+//
+// Only whole lines are searched; a line still being written waits until
+// its newline arrives. A file that gets shorter, or whose first few bytes
+// change, has been truncated, and is searched again from the top. A file
+// that's been replaced by a new one (log rotation, or an editor saving a
+// copy over it) is searched to the end first, and then the new one is
+// followed.
+pub struct Watch {
+    files: Vec<Tracked>,
+    notify: Notify,
+}
+
+impl Default for Watch {
+    fn default() -> Watch {
+        Watch::new()
+    }
+}
+
+impl Watch {
+    pub fn new() -> Watch {
+        #[cfg(target_os = "linux")]
+        if let Ok(inotify) = inotify::Inotify::new() {
+            return Watch {
+                files: Vec::new(),
+                notify: Notify::Inotify(inotify),
+            };
+        }
+        Watch {
+            files: Vec::new(),
+            notify: Notify::Poll,
+        }
+    }
+
+    // Starts following `path`, unless it's being followed already. It
+    // doesn't have to exist yet.
+    pub fn add(&mut self, path: PathBuf) {
+        if self.files.iter().any(|tracked| tracked.path == path) {
+            return;
+        }
+        // The directory is watched rather than the file, so that a file
+        // being created or replaced is noticed too.
+        #[cfg(target_os = "linux")]
+        if let Notify::Inotify(inotify) = &mut self.notify {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            // If it can't be watched, it's still looked at every so often.
+            let _ = inotify.watch_dir(dir);
+        }
+        self.files.push(Tracked {
+            path,
+            file: None,
+            id: None,
+            offset: 0,
+            lines: 0,
+            head: Vec::new(),
+            missing: false,
+        });
+    }
+
+    // Searches whatever has been added to each file since the last check.
+    // A file that's gone missing or been truncated is passed to `warn`.
+    // Files with nothing new that matches don't reach `sink` at all.
+    pub fn check<M, S>(
+        &mut self,
+        searcher: &Searcher,
+        matcher: &M,
+        sink: &mut S,
+        warn: &mut dyn FnMut(Warning),
+    ) -> io::Result<Stats>
+    where
+        M: Matcher + ?Sized,
+        S: Sink + ?Sized,
+    {
+        let mut stats = Stats::default();
+        for tracked in &mut self.files {
+            stats += tracked.check(searcher, matcher, sink, warn)?;
+        }
+        Ok(stats)
+    }
+
+    // Blocks until the files might have changed.
+    pub fn wait(&mut self) -> io::Result<()> {
+        match &mut self.notify {
+            #[cfg(target_os = "linux")]
+            Notify::Inotify(inotify) => inotify.wait(RESCAN_INTERVAL),
+            Notify::Poll => {
+                thread::sleep(POLL_INTERVAL);
+                Ok(())
+            }
+        }
+    }
+}
+
+enum Notify {
+    #[cfg(target_os = "linux")]
+    Inotify(inotify::Inotify),
+    Poll,
+}
+
+struct Tracked {
+    path: PathBuf,
+    // The file as it was opened. After a rotation this is still the old
+    // file, so whatever was written to it last can be read.
+    file: Option<File>,
+    // The device and inode `file` was opened from, to tell when `path`
+    // names a different file.
+    id: Option<(u64, u64)>,
+    // How far `file` has been searched, which is always the end of a line,
+    // and how many lines that was.
+    offset: u64,
+    lines: usize,
+    // Up to `FINGERPRINT_LEN` bytes from the start of `file`, as searched.
+    head: Vec<u8>,
+    // Whether we've already said the file isn't there.
+    missing: bool,
+}
+
+impl Tracked {
+    fn check<M, S>(
+        &mut self,
+        searcher: &Searcher,
+        matcher: &M,
+        sink: &mut S,
+        warn: &mut dyn FnMut(Warning),
+    ) -> io::Result<Stats>
+    where
+        M: Matcher + ?Sized,
+        S: Sink + ?Sized,
+    {
+        let mut stats = Stats::default();
+        let id = match fs::metadata(&self.path) {
+            Ok(metadata) => file_id(&metadata),
+            // Not created yet, or moved away and not replaced yet. Anything
+            // written to the old file in the meantime is still searched.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if !self.missing {
+                    self.missing = true;
+                    warn(Warning {
+                        path: self.path.clone(),
+                        error: e,
+                    });
+                }
+                if self.file.is_some() {
+                    stats += self.search_new(searcher, matcher, sink, warn)?;
+                }
+                return Ok(stats);
+            }
+            Err(e) => return Err(e),
+        };
+        self.missing = false;
+
+        if self.file.is_some() && id != self.id {
+            stats += self.search_new(searcher, matcher, sink, warn)?;
+            self.file = None;
+        }
+        if self.file.is_none() {
+            match File::open(&self.path) {
+                Ok(file) => self.file = Some(file),
+                // Gone again since we looked.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(stats),
+                Err(e) => return Err(e),
+            }
+            self.id = id;
+            self.offset = 0;
+            self.lines = 0;
+            self.head.clear();
+        }
+        stats += self.search_new(searcher, matcher, sink, warn)?;
+        Ok(stats)
+    }
+
+    // Searches the whole lines of `file` from `offset` on.
+    fn search_new<M, S>(
+        &mut self,
+        searcher: &Searcher,
+        matcher: &M,
+        sink: &mut S,
+        warn: &mut dyn FnMut(Warning),
+    ) -> io::Result<Stats>
+    where
+        M: Matcher + ?Sized,
+        S: Sink + ?Sized,
+    {
+        let mut file = match &self.file {
+            Some(file) => file,
+            None => return Ok(Stats::default()),
+        };
+        let len = file.metadata()?.len();
+        // Truncated and written again might leave it longer than before,
+        // but then it hardly ever starts the same way.
+        if len < self.offset || read_head(file, self.head.len())? != self.head {
+            warn(Warning {
+                path: self.path.clone(),
+                error: io::Error::other("file truncated"),
+            });
+            self.offset = 0;
+            self.lines = 0;
+            self.head.clear();
+        }
+        let end = last_line_end(file, self.offset, len)?;
+        if end == self.offset {
+            return Ok(Stats::default());
+        }
+
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut counted = CountLines {
+            inner: file.take(end - self.offset),
+            lines: 0,
+        };
+        let mut shifted = Shifted {
+            sink,
+            lines: self.lines,
+            bytes: self.offset as usize,
+            begun: false,
+        };
+        let stats = searcher.search_reader(
+            matcher,
+            BufReader::new(&mut counted),
+            &self.path,
+            &mut shifted,
+        )?;
+        // The sink may have stopped the search early, but the line numbers
+        // still have to be counted to the end.
+        io::copy(&mut counted, &mut io::sink())?;
+        self.offset = end;
+        self.lines += counted.lines;
+        if self.head.len() < FINGERPRINT_LEN {
+            self.head = read_head(file, FINGERPRINT_LEN.min(end as usize))?;
+        }
+        Ok(stats)
+    }
+}
+
+// The first `len` bytes of `file`, or as many as it has.
+fn read_head(mut file: &File, len: usize) -> io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(len);
+    file.seek(SeekFrom::Start(0))?;
+    file.take(len as u64).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+// Just past the last newline between `start` and `end`, or `start` if
+// there's none. The file is read backwards, so only the tail of a big file
+// is looked at.
+fn last_line_end(mut file: &File, start: u64, end: u64) -> io::Result<u64> {
+    let mut buf = [0; 8192];
+    let mut pos = end;
+    while pos > start {
+        let n = (pos - start).min(buf.len() as u64) as usize;
+        pos -= n as u64;
+        file.seek(SeekFrom::Start(pos))?;
+        match file.read_exact(&mut buf[..n]) {
+            Ok(()) => {}
+            // Truncated since we looked; the next check will see that.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(start),
+            Err(e) => return Err(e),
+        }
+        if let Some(i) = buf[..n].iter().rposition(|&b| b == b'\n') {
+            return Ok(pos + i as u64 + 1);
+        }
+    }
+    Ok(start)
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+// Without inodes, a rotation is only noticed if the new file is shorter.
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+// Counts the newlines read through it.
+struct CountLines<R> {
+    inner: R,
+    lines: usize,
+}
+
+impl<R: Read> Read for CountLines<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.lines += buf[..n].iter().filter(|&&b| b == b'\n').count();
+        Ok(n)
+    }
+}
+
+// Passes everything on to `sink`, with line numbers and byte offsets made
+// relative to the start of the file rather than where this search began.
+// The search is only begun and ended in `sink` if something is found, so
+// that every check of a file that hasn't changed isn't reported as one.
+struct Shifted<'a, S: ?Sized> {
+    sink: &'a mut S,
+    lines: usize,
+    bytes: usize,
+    begun: bool,
+}
+
+impl<S: Sink + ?Sized> Shifted<'_, S> {
+    fn begin_once(&mut self, path: &Path) -> io::Result<()> {
+        if !self.begun {
+            self.begun = true;
+            self.sink.begin(path)?;
+        }
+        Ok(())
+    }
+
+    fn shift<'m>(&self, m: &Match<'m>) -> Match<'m> {
+        Match {
+            line_number: m.line_number + self.lines,
+            byte_offset: m.byte_offset + self.bytes,
+            ..m.clone()
+        }
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for Shifted<'_, S> {
+    fn begin(&mut self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn matched(&mut self, path: &Path, m: &Match) -> io::Result<bool> {
+        self.begin_once(path)?;
+        let m = self.shift(m);
+        self.sink.matched(path, &m)
+    }
+
+    fn context(&mut self, path: &Path, m: &Match) -> io::Result<bool> {
+        self.begin_once(path)?;
+        let m = self.shift(m);
+        self.sink.context(path, &m)
+    }
+
+    fn context_break(&mut self) -> io::Result<()> {
+        self.sink.context_break()
+    }
+
+    fn binary_data(&mut self, path: &Path, byte_offset: usize) -> io::Result<bool> {
+        self.begin_once(path)?;
+        self.sink.binary_data(path, byte_offset + self.bytes)
+    }
+
+    fn end(&mut self, path: &Path, stats: &Stats) -> io::Result<()> {
+        if self.begun {
+            self.sink.end(path, stats)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::collections::HashSet;
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    // Changes to a watched directory, or to any file in it, that could mean
+    // a followed file has more in it or has been replaced.
+    const EVENTS: u32 = libc::IN_MODIFY
+        | libc::IN_CLOSE_WRITE
+        | libc::IN_ATTRIB
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO;
+
+    pub struct Inotify {
+        fd: libc::c_int,
+        dirs: HashSet<PathBuf>,
+    }
+
+    impl Inotify {
+        pub fn new() -> io::Result<Inotify> {
+            // Non-blocking, so that `wait` can read until there's nothing
+            // left; it blocks in `poll` instead.
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Inotify {
+                fd,
+                dirs: HashSet::new(),
+            })
+        }
+
+        pub fn watch_dir(&mut self, dir: &Path) -> io::Result<()> {
+            if self.dirs.contains(dir) {
+                return Ok(());
+            }
+            let name = CString::new(dir.as_os_str().as_bytes())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            if unsafe { libc::inotify_add_watch(self.fd, name.as_ptr(), EVENTS) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.dirs.insert(dir.to_path_buf());
+            Ok(())
+        }
+
+        // Waits for events, or for `timeout` to pass. Which files the
+        // events were about doesn't matter: every file is checked anyway,
+        // and that's just a `stat` for those that haven't changed.
+        pub fn wait(&mut self, timeout: Duration) -> io::Result<()> {
+            let mut poll = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut poll, 1, timeout.as_millis() as libc::c_int) };
+            if ready < 0 {
+                let e = io::Error::last_os_error();
+                // A signal, e.g. the terminal being resized.
+                if e.kind() == io::ErrorKind::Interrupted {
+                    return Ok(());
+                }
+                return Err(e);
+            }
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = unsafe { libc::read(self.fd, buf.as_mut_ptr().cast(), buf.len()) };
+                if n == 0 {
+                    return Ok(());
+                }
+                if n < 0 {
+                    let e = io::Error::last_os_error();
+                    return match e.kind() {
+                        // Everything's been read.
+                        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => Ok(()),
+                        _ => Err(e),
+                    };
+                }
+            }
+        }
+    }
+
+    impl Drop for Inotify {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.fd);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FnSink;
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    // The lines that matched, and any warnings, in the order they came.
+    fn check(watch: &mut Watch) -> Vec<String> {
+        let lines = RefCell::new(Vec::new());
+        watch
+            .check(
+                &Searcher::new(),
+                "x",
+                &mut FnSink(|_: &Path, m: &Match| {
+                    lines
+                        .borrow_mut()
+                        .push(format!("{}:{}", m.line_number, m.line));
+                    Ok(true)
+                }),
+                &mut |warning| lines.borrow_mut().push(warning.error.to_string()),
+            )
+            .unwrap();
+        lines.into_inner()
+    }
+
+    fn append(path: &Path, text: &str) {
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn appends_truncation_and_rotation() {
+        let dir = env::temp_dir().join(format!("minigrep-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("app.log");
+        let mut watch = Watch::new();
+        watch.add(log.clone());
+        let missing = vec!["No such file or directory (os error 2)"];
+        assert_eq!(missing, check(&mut watch));
+
+        // The last line isn't searched until it's finished.
+        fs::write(&log, "a x\nb\nc x").unwrap();
+        assert_eq!(vec!["1:a x"], check(&mut watch));
+        append(&log, "\nd x\n");
+        assert_eq!(vec!["3:c x", "4:d x"], check(&mut watch));
+        assert!(check(&mut watch).is_empty());
+
+        fs::write(&log, "x again\n").unwrap();
+        assert_eq!(vec!["file truncated", "1:x again"], check(&mut watch));
+
+        // Just as long as before, or longer, but not the same file.
+        fs::write(&log, "x and then some\n").unwrap();
+        assert_eq!(
+            vec!["file truncated", "1:x and then some"],
+            check(&mut watch)
+        );
+        fs::write(&log, "x and then some\n").unwrap();
+        assert!(check(&mut watch).is_empty());
+
+        // Whatever made it into the old file before it was moved still
+        // counts.
+        append(&log, "old x\n");
+        fs::rename(&log, dir.join("app.log.1")).unwrap();
+        fs::write(&log, "new x\n").unwrap();
+        assert_eq!(vec!["2:old x", "1:new x"], check(&mut watch));
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            vec!["No such file or directory (os error 2)"],
+            check(&mut watch)
+        );
+        assert!(check(&mut watch).is_empty());
+    }
+
+    // Counts the searches a sink is told about.
+    struct Searches(usize, usize);
+
+    impl Sink for Searches {
+        fn begin(&mut self, _: &Path) -> io::Result<()> {
+            self.0 += 1;
+            Ok(())
+        }
+
+        fn matched(&mut self, _: &Path, _: &Match) -> io::Result<bool> {
+            Ok(true)
+        }
+
+        fn end(&mut self, _: &Path, _: &Stats) -> io::Result<()> {
+            self.1 += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn only_searches_with_matches_are_reported() {
+        let path = env::temp_dir().join(format!("minigrep-watch-sink-{}", std::process::id()));
+        fs::write(&path, "a x\n").unwrap();
+        let mut watch = Watch::new();
+        watch.add(path.clone());
+        let mut sink = Searches(0, 0);
+        let check = |watch: &mut Watch, sink: &mut Searches| {
+            watch
+                .check(&Searcher::new(), "x", sink, &mut |w| panic!("{}", w))
+                .unwrap();
+        };
+        check(&mut watch, &mut sink);
+        check(&mut watch, &mut sink);
+        append(&path, "no match\n");
+        check(&mut watch, &mut sink);
+        assert_eq!((1, 1), (sink.0, sink.1));
+        append(&path, "b x\n");
+        check(&mut watch, &mut sink);
+        assert_eq!((2, 2), (sink.0, sink.1));
+        fs::remove_file(&path).unwrap();
+    }
+}